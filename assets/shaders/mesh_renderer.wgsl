@@ -1,36 +1,105 @@
-#import bevy_pbr::mesh_functions::{get_world_from_local, mesh_position_local_to_clip}
+// Triplanar texturing of the terrain, see `terrain_material.rs`
+
+#import bevy_pbr::{
+    forward_io::{VertexOutput, FragmentOutput},
+    mesh_functions,
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::{alpha_discard, apply_pbr_lighting, main_pass_post_lighting_processing},
+    view_transformations::position_world_to_clip,
+}
 
 struct Vertex {
+    @builtin(instance_index) instance_index: u32,
     @location(0) position: vec3<f32>,
     @location(1) normal: vec3<f32>,
-    @location(2) uv: vec2<f32>,
+    @location(8) weights_a: vec4<f32>,
+    @location(9) weights_b: vec4<f32>,
+};
 
-    @location(3) i_pos_scale: vec4<f32>,
-    @location(4) i_color: vec4<f32>,
+struct TerrainVertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) weights_a: vec4<f32>,
+    @location(3) weights_b: vec4<f32>,
+    @location(4) @interpolate(flat) instance_index: u32,
 };
 
-struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
-    @location(0) color: vec4<f32>,
+struct Triplanar {
+    texture_scale: f32,
+    blend_sharpness: f32,
 };
 
+@group(2) @binding(100) var<uniform> triplanar: Triplanar;
+@group(2) @binding(101) var textures: texture_2d_array<f32>;
+@group(2) @binding(102) var textures_sampler: sampler;
+
 @vertex
-fn vertex(vertex: Vertex) -> VertexOutput {
-    let position = vertex.position * vertex.i_pos_scale.w + vertex.i_pos_scale.xyz;
-    var out: VertexOutput;
-    // NOTE: Passing 0 as the instance_index to get_world_from_local() is a hack
-    // for this example as the instance_index builtin would map to the wrong
-    // index in the Mesh array. This index could be passed in via another
-    // uniform instead but it's unnecessary for the example.
-    out.clip_position = mesh_position_local_to_clip(
-        get_world_from_local(0u),
-        vec4<f32>(position, 1.0)
+fn vertex(vertex: Vertex) -> TerrainVertexOutput {
+    var out: TerrainVertexOutput;
+
+    let world_from_local = mesh_functions::get_world_from_local(vertex.instance_index);
+    out.world_position = mesh_functions::mesh_position_local_to_world(
+        world_from_local,
+        vec4<f32>(vertex.position, 1.0)
     );
-    out.color = vertex.i_color;
+    out.position = position_world_to_clip(out.world_position.xyz);
+    out.world_normal = mesh_functions::mesh_normal_local_to_world(
+        vertex.normal,
+        vertex.instance_index
+    );
+    out.weights_a = vertex.weights_a;
+    out.weights_b = vertex.weights_b;
+    out.instance_index = vertex.instance_index;
+
     return out;
 }
 
+/// Colour of a material layer, projected along the 3 axes
+fn triplanar_sample(layer: u32, position: vec3<f32>, blend: vec3<f32>) -> vec4<f32> {
+    let x = textureSample(textures, textures_sampler, position.zy, layer);
+    let y = textureSample(textures, textures_sampler, position.xz, layer);
+    let z = textureSample(textures, textures_sampler, position.xy, layer);
+    return x * blend.x + y * blend.y + z * blend.z;
+}
+
 @fragment
-fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
-    return in.color;
+fn fragment(in: TerrainVertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
+    var standard_in: VertexOutput;
+    standard_in.position = in.position;
+    standard_in.world_position = in.world_position;
+    standard_in.world_normal = in.world_normal;
+#ifdef VERTEX_COLORS
+    // Vertex colours are the fallback for missing textures, they would tint them
+    standard_in.color = vec4<f32>(1.0);
+#endif
+#ifdef VERTEX_OUTPUT_INSTANCE_INDEX
+    standard_in.instance_index = in.instance_index;
+#endif
+
+    var pbr_input = pbr_input_from_standard_material(standard_in, is_front);
+
+    let normal = normalize(in.world_normal);
+    var blend = pow(abs(normal), vec3<f32>(triplanar.blend_sharpness));
+    blend /= blend.x + blend.y + blend.z;
+
+    let position = in.world_position.xyz * triplanar.texture_scale;
+    var weights = array<f32, 8>(
+        in.weights_a.x, in.weights_a.y, in.weights_a.z, in.weights_a.w,
+        in.weights_b.x, in.weights_b.y, in.weights_b.z, in.weights_b.w,
+    );
+
+    var color = vec4<f32>(0.0);
+    let layers = min(textureNumLayers(textures), 8u);
+    for (var layer = 0u; layer < layers; layer++) {
+        color += triplanar_sample(layer, position, blend) * weights[layer];
+    }
+    pbr_input.material.base_color *= color;
+
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
 }
@@ -1,6 +1,13 @@
 use bevy::{color::palettes::css::BLACK, prelude::*};
 
-use crate::player::Player;
+use crate::{
+    map_generator::{
+        endless_terrain::{ChunkMap, ChunkState},
+        terrain_raycast::TerrainRaycast,
+    },
+    player::{Player, EDIT_REACH},
+    settings::key_bindings::{Action, ActionInput},
+};
 
 #[derive(Component)]
 pub struct F3Info;
@@ -11,6 +18,15 @@ pub struct PlayerCoordF3;
 #[derive(Component)]
 pub struct ChunkCoordF3;
 
+#[derive(Component)]
+pub struct VoxelMemoryF3;
+
+#[derive(Component)]
+pub struct SkippedChunksF3;
+
+#[derive(Component)]
+pub struct TargetedTerrainF3;
+
 fn dispay_info(mut commands: Commands) {
     let style = TextStyle {
         font_size: 20.0,
@@ -57,15 +73,48 @@ fn dispay_info(mut commands: Commands) {
                 .with_background_color(text_bg_color),
                 ChunkCoordF3,
             ));
+
+            // -------------------- Voxel memory --------------------
+            builder.spawn((
+                TextBundle::from_sections([
+                    TextSection::new("Voxel memory [", style.clone()),
+                    TextSection::new("0", style.clone()),
+                    TextSection::new(" KiB]", style.clone()),
+                ])
+                .with_background_color(text_bg_color),
+                VoxelMemoryF3,
+            ));
+
+            // -------------------- Skipped chunks --------------------
+            builder.spawn((
+                TextBundle::from_sections([
+                    TextSection::new("Skipped chunks [", style.clone()),
+                    TextSection::new("0 empty 0 solid", style.clone()),
+                    TextSection::new("]", style.clone()),
+                ])
+                .with_background_color(text_bg_color),
+                SkippedChunksF3,
+            ));
+
+            // -------------------- Targeted terrain --------------------
+            builder.spawn((
+                TextBundle::from_sections([
+                    TextSection::new("Looking at [", style.clone()),
+                    TextSection::new("nothing", style.clone()),
+                    TextSection::new("]", style.clone()),
+                ])
+                .with_background_color(text_bg_color),
+                TargetedTerrainF3,
+            ));
         });
 }
 
 pub(super) fn toggle_text_visibility(
     mut commands: Commands,
-    keyboard_input: Res<ButtonInput<KeyCode>>,
+    action_input: ActionInput,
     f3_info_q: Query<Entity, With<F3Info>>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::F3) {
+    if action_input.just_pressed(Action::ToggleDebug) {
         if let Ok(f3_info_e) = f3_info_q.get_single() {
             commands.entity(f3_info_e).despawn_recursive();
         } else {
@@ -111,3 +160,73 @@ pub(super) fn update_player_position(
         );
     }
 }
+
+/// Memory used by the density and materials of the loaded chunks
+pub(super) fn update_voxel_memory(
+    chunk_map: Res<ChunkMap>,
+    mut voxel_memory_f3_q: Query<&mut Text, With<VoxelMemoryF3>>,
+) {
+    if let Ok(mut voxel_memory_text) = voxel_memory_f3_q.get_single_mut() {
+        let bytes: usize = chunk_map
+            .0
+            .values()
+            .filter_map(|chunk| chunk.voxel_grid.as_ref())
+            .map(|voxel_grid| voxel_grid.memory_usage())
+            .sum();
+
+        voxel_memory_text.sections[1].value = format!("{}", bytes / 1024);
+    }
+}
+
+/// Chunks which were neither meshed nor spawned for being entirely above or below the surface
+pub(super) fn update_skipped_chunks(
+    chunk_map: Res<ChunkMap>,
+    mut skipped_chunks_f3_q: Query<&mut Text, With<SkippedChunksF3>>,
+) {
+    if let Ok(mut skipped_chunks_text) = skipped_chunks_f3_q.get_single_mut() {
+        let count = |state: ChunkState| {
+            chunk_map
+                .0
+                .values()
+                .filter(|chunk| chunk.state == state)
+                .count()
+        };
+
+        skipped_chunks_text.sections[1].value = format!(
+            "{} empty {} solid",
+            count(ChunkState::Empty),
+            count(ChunkState::Solid)
+        );
+    }
+}
+
+/// Terrain the player is looking at, within editing reach
+pub(super) fn update_targeted_terrain(
+    player_q: Query<&Transform, With<Player>>,
+    terrain_raycast: TerrainRaycast,
+    mut targeted_terrain_f3_q: Query<&mut Text, With<TargetedTerrainF3>>,
+) {
+    if let Ok(mut targeted_terrain_text) = targeted_terrain_f3_q.get_single_mut() {
+        let Ok(player_transform) = player_q.get_single() else {
+            return;
+        };
+
+        let ray = Ray3d {
+            origin: player_transform.translation,
+            direction: player_transform.forward(),
+        };
+        targeted_terrain_text.sections[1].value = match terrain_raycast.cast(ray, EDIT_REACH) {
+            Some(hit) => format!(
+                "{:.2} {:.2} {:.2} in chunk {} {} {}, {:.1}m away",
+                hit.position.x,
+                hit.position.y,
+                hit.position.z,
+                hit.chunk_coord.x,
+                hit.chunk_coord.y,
+                hit.chunk_coord.z,
+                hit.distance
+            ),
+            None => "nothing".to_owned(),
+        };
+    }
+}
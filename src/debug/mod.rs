@@ -2,9 +2,15 @@ use bevy::{
     color::palettes::css::{BLUE, GREEN, RED, WHITE},
     prelude::*,
 };
-use f3_info::{toggle_text_visibility, update_curr_chunk, update_player_position};
+use f3_info::{
+    toggle_text_visibility, update_curr_chunk, update_player_position, update_skipped_chunks,
+    update_targeted_terrain, update_voxel_memory,
+};
 
-use crate::map_generator::endless_terrain::{ChunkMap, CHUNK_SIZE};
+use crate::{
+    map_generator::endless_terrain::{ChunkMap, CHUNK_SIZE},
+    settings::debug::DebugSetting,
+};
 
 mod f3_info;
 
@@ -17,10 +23,14 @@ impl Plugin for DebugPlugin {
             .add_systems(
                 Update,
                 (
-                    chunk_gizmos,
+                    chunk_gizmos
+                        .run_if(|debug_cfg: Res<DebugSetting>| debug_cfg.display_chunk_gizmos),
                     toggle_text_visibility,
                     update_curr_chunk,
                     update_player_position,
+                    update_voxel_memory,
+                    update_skipped_chunks,
+                    update_targeted_terrain,
                 ),
             );
     }
@@ -3,6 +3,8 @@ use bevy::{
     window::{CursorGrabMode, PrimaryWindow},
 };
 
+use crate::settings::key_bindings::{Action, ActionInput};
+
 pub struct FlyCamPlugin;
 
 impl Plugin for FlyCamPlugin {
@@ -15,7 +17,8 @@ impl Plugin for FlyCamPlugin {
 #[derive(Component)]
 pub struct FlyCam;
 
-fn toggle_grab_cursor(window: &mut Window) {
+/// Grab the cursor when it is free, release it otherwise
+pub fn toggle_grab_cursor(window: &mut Window) {
     match window.cursor.grab_mode {
         CursorGrabMode::None => {
             window.cursor.grab_mode = CursorGrabMode::Confined;
@@ -29,11 +32,11 @@ fn toggle_grab_cursor(window: &mut Window) {
 }
 
 fn cursor_grab(
-    keys: Res<ButtonInput<KeyCode>>,
+    action_input: ActionInput,
     mut primary_window: Query<&mut Window, With<PrimaryWindow>>,
 ) {
     if let Ok(mut window) = primary_window.get_single_mut() {
-        if keys.just_pressed(KeyCode::AltLeft) {
+        if action_input.just_pressed(Action::ToggleCursor) {
             toggle_grab_cursor(&mut window);
         }
     } else {
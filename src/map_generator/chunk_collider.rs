@@ -0,0 +1,31 @@
+//! Trimesh colliders of the terrain chunks, only built with the `physics` feature.
+//!
+//! Colliders live on the entity of their chunk, so they are spawned, rebuilt and despawned along
+//! with its mesh.
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use super::chunk_mesher::ChunkMeshData;
+
+pub struct ChunkColliderPlugin;
+
+impl Plugin for ChunkColliderPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(RapierPhysicsPlugin::<NoUserData>::default());
+    }
+}
+
+/// Static collider matching the triangles of a chunk mesh, `None` if it has none
+pub fn chunk_collider(mesh_data: &ChunkMeshData) -> Option<Collider> {
+    if mesh_data.indices.is_empty() {
+        return None;
+    }
+
+    let indices = mesh_data
+        .indices
+        .chunks_exact(3)
+        .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+        .collect();
+    Some(Collider::trimesh(mesh_data.positions.clone(), indices))
+}
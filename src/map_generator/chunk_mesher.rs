@@ -0,0 +1,619 @@
+use bevy::{
+    color::ColorToComponents,
+    math::{IVec3, UVec3, Vec3},
+    render::{
+        mesh::{Indices, Mesh, PrimitiveTopology},
+        render_asset::RenderAssetUsages,
+    },
+};
+
+use crate::settings::render::NormalMode;
+
+use super::{
+    lod::{snap_to_coarser_neighbours, LodNeighbours},
+    marching_table::{EDGES, TRIANGULATIONS, VERTICES},
+    noise_generator::VoxelGrid,
+    terrain_material::{ATTRIBUTE_MATERIAL_WEIGHTS_A, ATTRIBUTE_MATERIAL_WEIGHTS_B},
+    voxel_material::VoxelMaterial,
+    NoiseGenerator,
+};
+
+/// Plain mesh data of a chunk, independent from Bevy's `World`
+#[derive(Debug, Default, Clone)]
+pub struct ChunkMeshData {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    /// Material of each vertex, the one of the solid end of the edge it lies on
+    pub materials: Vec<VoxelMaterial>,
+    pub indices: Vec<u32>,
+}
+
+impl ChunkMeshData {
+    pub fn into_mesh(self) -> Mesh {
+        let mut mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::RENDER_WORLD,
+        );
+
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_indices(Indices::U32(self.indices));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+
+        let colors: Vec<[f32; 4]> = self
+            .materials
+            .iter()
+            .map(|material| material.color().to_linear().to_f32_array())
+            .collect();
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+
+        // One-hot weights, blended between the vertices of a triangle by the rasterizer
+        let (weights_a, weights_b): (Vec<[f32; 4]>, Vec<[f32; 4]>) = self
+            .materials
+            .iter()
+            .map(|&material| {
+                let mut weights = [0.0; 8];
+                weights[material as usize] = 1.0;
+                (
+                    [weights[0], weights[1], weights[2], weights[3]],
+                    [weights[4], weights[5], weights[6], weights[7]],
+                )
+            })
+            .unzip();
+        mesh.insert_attribute(ATTRIBUTE_MATERIAL_WEIGHTS_A, weights_a);
+        mesh.insert_attribute(ATTRIBUTE_MATERIAL_WEIGHTS_B, weights_b);
+
+        mesh
+    }
+}
+
+/// Turns a `VoxelGrid` into a triangle mesh using marching cubes
+pub struct ChunkMesher<'a> {
+    pub isovalue: f32,
+    pub normal_mode: NormalMode,
+    /// Density function used for `NormalMode::Gradient`. Without one, gradients are estimated
+    /// from the grid samples instead.
+    generator: Option<&'a dyn NoiseGenerator>,
+    /// Level of detail of the neighbouring chunks, used to stitch the borders shared with coarser
+    /// chunks
+    lods: Option<LodNeighbours>,
+}
+
+impl<'a> ChunkMesher<'a> {
+    pub fn new(isovalue: f32, normal_mode: NormalMode) -> Self {
+        Self {
+            isovalue,
+            normal_mode,
+            generator: None,
+            lods: None,
+        }
+    }
+
+    pub fn with_generator(mut self, generator: &'a dyn NoiseGenerator) -> Self {
+        self.generator = Some(generator);
+        self
+    }
+
+    pub fn with_lods(mut self, lods: LodNeighbours) -> Self {
+        self.lods = Some(lods);
+        self
+    }
+
+    /// March every cube of the grid. Positions are local to the grid's origin.
+    pub fn mesh(&self, voxel_grid: &VoxelGrid) -> ChunkMeshData {
+        // No cube of a chunk entirely above or below the surface has any triangle
+        if !voxel_grid.straddles(self.isovalue) {
+            return ChunkMeshData::default();
+        }
+
+        // Borders shared with coarser chunks must be sampled like them to line up
+        let snapped_grid;
+        let voxel_grid = match &self.lods {
+            Some(lods) => {
+                let mut grid = voxel_grid.clone();
+                snap_to_coarser_neighbours(&mut grid, lods);
+                snapped_grid = grid;
+                &snapped_grid
+            }
+            None => voxel_grid,
+        };
+
+        let size = voxel_grid.size;
+        let mut builder = ChunkMeshBuilder::new(size);
+
+        for z in 0..(size - 1) {
+            for y in 0..(size - 1) {
+                for x in 0..(size - 1) {
+                    march_cube((x, y, z), voxel_grid, self.isovalue, &mut builder);
+                }
+            }
+        }
+
+        if let Some(lods) = &self.lods {
+            builder.fill_transitions(voxel_grid, lods, self.isovalue);
+        }
+
+        // Grid units to world units
+        let step = voxel_grid.step() as f32;
+        builder.positions.iter_mut().for_each(|pos| *pos *= step);
+
+        let normals = match (self.normal_mode, self.generator) {
+            (NormalMode::Flat, _) => {
+                builder.unweld();
+                builder.averaged_normals()
+            }
+            (NormalMode::AveragedFace, _) => builder.averaged_normals(),
+            (NormalMode::Gradient, Some(generator)) => {
+                // The generator is sampled in world space
+                let origin = voxel_grid.origin();
+                builder.gradient_normals(|pos| generator.gradient(origin + pos))
+            }
+            (NormalMode::Gradient, None) => builder
+                .grid_gradients
+                .iter()
+                .map(|gradient| gradient.normalize_or_zero())
+                .collect(),
+        };
+
+        ChunkMeshData {
+            positions: builder.positions,
+            normals,
+            materials: builder.materials,
+            indices: builder.indices,
+        }
+    }
+}
+
+/// Perform a linear interpolation along the edge
+fn interpolate_verts(p1: Vec3, p2: Vec3, s1: f32, s2: f32, isovalue: f32) -> Vec3 {
+    let t = (isovalue - s1) / (s2 - s1);
+    p1 + (t * (p2 - p1))
+}
+
+/// Accumulates the vertices and triangles of a chunk mesh.
+///
+/// Every edge of the voxel grid that is crossed by the surface yields exactly one vertex, which is
+/// shared by all triangles (of all neighbouring cubes) that touch that edge.
+struct ChunkMeshBuilder {
+    positions: Vec<Vec3>,
+    /// Density gradient at each vertex, interpolated from the gradients of the edge's corners
+    grid_gradients: Vec<Vec3>,
+    materials: Vec<VoxelMaterial>,
+    indices: Vec<u32>,
+    /// Vertex index of each grid edge, addressed by `3 * corner_idx + axis` where `corner_idx` is
+    /// the 1D index of the edge's lower corner. `u32::MAX` marks an edge without vertex yet.
+    edge_cache: Vec<u32>,
+    grid_size: usize,
+}
+
+impl ChunkMeshBuilder {
+    fn new(grid_size: usize) -> Self {
+        Self {
+            positions: Vec::new(),
+            grid_gradients: Vec::new(),
+            materials: Vec::new(),
+            indices: Vec::new(),
+            edge_cache: vec![u32::MAX; grid_size.pow(3) * 3],
+            grid_size,
+        }
+    }
+
+    /// Get the index of the vertex lying on the edge between 2 adjacent grid points,
+    /// creating it if this edge was not visited before
+    fn edge_vertex(
+        &mut self,
+        voxel_grid: &VoxelGrid,
+        corner_a: UVec3,
+        corner_b: UVec3,
+        isovalue: f32,
+    ) -> u32 {
+        let lower = corner_a.min(corner_b);
+        // Edges are interpolated from their lower corner, so neighbouring chunks sharing one get
+        // the exact same vertex
+        let (corner_a, corner_b) = match corner_a == lower {
+            true => (corner_a, corner_b),
+            false => (corner_b, corner_a),
+        };
+        let axis = match corner_b - lower {
+            UVec3 { x: 1, .. } => 0,
+            UVec3 { y: 1, .. } => 1,
+            _ => 2,
+        };
+        let corner_idx = VoxelGrid::to_1d(
+            lower.x as usize,
+            lower.y as usize,
+            lower.z as usize,
+            self.grid_size,
+        );
+        let key = corner_idx * 3 + axis;
+
+        if self.edge_cache[key] == u32::MAX {
+            let a = (
+                corner_a.x as usize,
+                corner_a.y as usize,
+                corner_a.z as usize,
+            );
+            let b = (
+                corner_b.x as usize,
+                corner_b.y as usize,
+                corner_b.z as usize,
+            );
+
+            // Read value of 2 points
+            let sa = voxel_grid.read(a.0, a.1, a.2);
+            let sb = voxel_grid.read(b.0, b.1, b.2);
+
+            // Find where the surface crosses the edge
+            let vertex =
+                interpolate_verts(corner_a.as_vec3(), corner_b.as_vec3(), sa, sb, isovalue);
+
+            // Gradients are interpolated the same way as the position
+            let gradient = interpolate_verts(
+                voxel_grid.gradient(a.0, a.1, a.2),
+                voxel_grid.gradient(b.0, b.1, b.2),
+                sa,
+                sb,
+                isovalue,
+            );
+
+            // The surface is made of what is below it
+            let material = match sa < isovalue {
+                true => voxel_grid.material(a.0, a.1, a.2),
+                false => voxel_grid.material(b.0, b.1, b.2),
+            };
+
+            self.edge_cache[key] = self.positions.len() as u32;
+            self.positions.push(vertex);
+            self.grid_gradients.push(gradient);
+            self.materials.push(material);
+        }
+
+        self.edge_cache[key]
+    }
+
+    /// Index of the vertex already created on the edge between 2 adjacent grid points
+    fn cached_vertex(&self, a: [usize; 3], b: [usize; 3]) -> Option<u32> {
+        let lower = [a[0].min(b[0]), a[1].min(b[1]), a[2].min(b[2])];
+        let axis = (0..3).find(|&axis| a[axis] != b[axis])?;
+        let corner_idx = VoxelGrid::to_1d(lower[0], lower[1], lower[2], self.grid_size);
+
+        match self.edge_cache[corner_idx * 3 + axis] {
+            u32::MAX => None,
+            idx => Some(idx),
+        }
+    }
+
+    /// Close the gaps left on the faces shared with coarser neighbours.
+    ///
+    /// On such a face, the coarser chunk's surface follows a straight segment across each of its
+    /// cells while ours follows a polyline through the finer cells. Both start and end at the same
+    /// points since the border was snapped to the coarse lattice, so the gap between them is a flat
+    /// polygon on the face which is filled here with a triangle fan.
+    fn fill_transitions(&mut self, voxel_grid: &VoxelGrid, lods: &LodNeighbours, isovalue: f32) {
+        let size = voxel_grid.size;
+        let last = size - 1;
+        let inside = |p: [usize; 3]| voxel_grid.read(p[0], p[1], p[2]) < isovalue;
+
+        for (axis, side) in [0, 1, 2]
+            .into_iter()
+            .flat_map(|axis| [(axis, -1), (axis, 1)])
+        {
+            let mut dir = IVec3::ZERO;
+            dir[axis] = side;
+
+            let ratio = lods.ratio(dir, size);
+            if ratio == 1 {
+                continue;
+            }
+
+            // 3D grid point of the (u, v) position on the face
+            let (u_axis, v_axis) = ((axis + 1) % 3, (axis + 2) % 3);
+            let point = |u: usize, v: usize| {
+                let mut p = [0; 3];
+                p[axis] = if side < 0 { 0 } else { last };
+                p[u_axis] = u;
+                p[v_axis] = v;
+                p
+            };
+
+            for cv in (0..last).step_by(ratio) {
+                for cu in (0..last).step_by(ratio) {
+                    let polygon =
+                        trace_transition_cell((cu, cv), ratio, |u, v| inside(point(u, v)));
+                    let Some(polygon) = polygon else {
+                        continue;
+                    };
+
+                    let vertices: Option<Vec<u32>> = polygon
+                        .iter()
+                        .map(|&((u0, v0), (u1, v1))| {
+                            self.cached_vertex(point(u0, v0), point(u1, v1))
+                        })
+                        .collect();
+                    let Some(vertices) = vertices else {
+                        continue;
+                    };
+
+                    // The fill lies in the plane of the face, so it's made double sided
+                    for pair in vertices[1..].windows(2) {
+                        self.indices.extend([vertices[0], pair[0], pair[1]]);
+                        self.indices.extend([vertices[0], pair[1], pair[0]]);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Per-vertex normals obtained by averaging the normals of every face sharing the vertex
+    fn averaged_normals(&self) -> Vec<Vec3> {
+        let mut normals = vec![Vec3::ZERO; self.positions.len()];
+
+        for tri in self.indices.chunks_exact(3) {
+            let (a, b, c) = (tri[0] as usize, tri[1] as usize, tri[2] as usize);
+            let (pa, pb, pc) = (self.positions[a], self.positions[b], self.positions[c]);
+
+            // Unnormalized so bigger faces weight more
+            let normal = (pb - pa).cross(pc - pa);
+            normals[a] += normal;
+            normals[b] += normal;
+            normals[c] += normal;
+        }
+
+        normals
+            .into_iter()
+            .map(|normal| normal.normalize_or_zero())
+            .collect()
+    }
+
+    /// Per-vertex normals taken from the gradient of the density field at each vertex.
+    ///
+    /// Density grows from solid toward air, so the gradient already points outward.
+    fn gradient_normals(&self, gradient: impl Fn(Vec3) -> Vec3) -> Vec<Vec3> {
+        self.positions
+            .iter()
+            .map(|&pos| gradient(pos).normalize_or_zero())
+            .collect()
+    }
+
+    /// Duplicate shared vertices so that every triangle owns its 3 vertices
+    fn unweld(&mut self) {
+        self.positions = self
+            .indices
+            .iter()
+            .map(|&idx| self.positions[idx as usize])
+            .collect();
+        self.grid_gradients = self
+            .indices
+            .iter()
+            .map(|&idx| self.grid_gradients[idx as usize])
+            .collect();
+        self.materials = self
+            .indices
+            .iter()
+            .map(|&idx| self.materials[idx as usize])
+            .collect();
+        self.indices = (0..self.positions.len() as u32).collect();
+    }
+}
+
+/// Edge between 2 adjacent points of a face, lower point first
+type FaceEdge = ((usize, usize), (usize, usize));
+
+/// Follow the surface through the fine cells of the coarse face cell starting at `(cu, cv)` and
+/// spanning `ratio` fine cells per side.
+///
+/// Returns the crossed fine edges in order, from the coarse cell's border back to it. `None` when
+/// the fine polyline matches the coarse segment or when the coarse cell is ambiguous.
+fn trace_transition_cell(
+    (cu, cv): (usize, usize),
+    ratio: usize,
+    inside: impl Fn(usize, usize) -> bool,
+) -> Option<Vec<FaceEdge>> {
+    let (eu, ev) = (cu + ratio, cv + ratio);
+    let crossed = |((u0, v0), (u1, v1)): FaceEdge| inside(u0, v0) != inside(u1, v1);
+    let on_border = |((u0, v0), (u1, v1)): FaceEdge| {
+        (v0 == v1 && (v0 == cv || v0 == ev)) || (u0 == u1 && (u0 == cu || u0 == eu))
+    };
+
+    // Only cells crossed exactly twice have a single, unambiguous segment
+    let corners = [(cu, cv), (eu, cv), (eu, ev), (cu, ev)];
+    let coarse_crossings = (0..4)
+        .filter(|&i| {
+            let (a, b) = (corners[i], corners[(i + 1) % 4]);
+            inside(a.0, a.1) != inside(b.0, b.1)
+        })
+        .count();
+    if coarse_crossings != 2 {
+        return None;
+    }
+
+    let cell_edges = |(su, sv): (usize, usize)| -> [FaceEdge; 4] {
+        [
+            ((su, sv), (su + 1, sv)),
+            ((su + 1, sv), (su + 1, sv + 1)),
+            ((su, sv + 1), (su + 1, sv + 1)),
+            ((su, sv), (su, sv + 1)),
+        ]
+    };
+
+    // Find the fine cell where the surface enters the coarse cell
+    let border_cells = (cu..eu)
+        .map(|u| (u, cv))
+        .chain((cv..ev).map(|v| (eu - 1, v)))
+        .chain((cu..eu).map(|u| (u, ev - 1)))
+        .chain((cv..ev).map(|v| (cu, v)));
+    let (mut cell, mut edge_in) = border_cells
+        .flat_map(|cell| cell_edges(cell).map(|edge| (cell, edge)))
+        .find(|&(_, edge)| on_border(edge) && crossed(edge))?;
+
+    let mut polyline = vec![edge_in];
+    for _ in 0..ratio * ratio {
+        let mut crossed_edges = cell_edges(cell).into_iter().filter(|&edge| crossed(edge));
+        let (Some(a), Some(b), None) = (
+            crossed_edges.next(),
+            crossed_edges.next(),
+            crossed_edges.next(),
+        ) else {
+            return None;
+        };
+        let edge_out = if a == edge_in { b } else { a };
+        polyline.push(edge_out);
+
+        if on_border(edge_out) {
+            // Only the 2 ends means the fine and coarse segments are the same
+            return (polyline.len() > 2).then_some(polyline);
+        }
+
+        // Step into the fine cell on the other side of the edge
+        let ((u0, v0), (u1, _)) = edge_out;
+        cell = match (u0 == u1, cell) {
+            (true, (su, sv)) if su == u0 => (su - 1, sv),
+            (true, (su, sv)) => (su + 1, sv),
+            (false, (su, sv)) if sv == v0 => (su, sv - 1),
+            (false, (su, sv)) => (su, sv + 1),
+        };
+        edge_in = edge_out;
+    }
+
+    None
+}
+
+fn march_cube(
+    (x, y, z): (usize, usize, usize),
+    voxel_grid: &VoxelGrid,
+    isovalue: f32,
+    builder: &mut ChunkMeshBuilder,
+) {
+    let triangulation = get_triangulation((x, y, z), voxel_grid, isovalue);
+
+    for triangle in triangulation.chunks_exact(3) {
+        if triangle[0].is_negative() {
+            break;
+        }
+
+        let mut triangle_indices = [0u32; 3];
+        for (i, &edge_idx) in triangle.iter().enumerate() {
+            // Get the 2 vertices' local position from edge
+            let vertex_positions = EDGES[edge_idx as usize];
+
+            // Get the vertex's position value
+            let (xa, ya, za) = VERTICES[vertex_positions.0];
+            let (xb, yb, zb) = VERTICES[vertex_positions.1];
+
+            // Calculate the actual in-grid position of 2 edge's vertices
+            let corner_pos_a = UVec3::new((x + xa) as u32, (y + ya) as u32, (z + za) as u32);
+            let corner_pos_b = UVec3::new((x + xb) as u32, (y + yb) as u32, (z + zb) as u32);
+
+            triangle_indices[i] =
+                builder.edge_vertex(voxel_grid, corner_pos_a, corner_pos_b, isovalue);
+        }
+
+        // Reverse the winding order so the faces point outward
+        builder.indices.extend(triangle_indices.iter().rev());
+    }
+}
+
+fn get_triangulation(
+    (x, y, z): (usize, usize, usize),
+    voxel_grid: &VoxelGrid,
+    isovalue: f32,
+) -> [i8; 15] {
+    let mut cube_idx = 0b00000000;
+
+    #[allow(clippy::needless_range_loop)]
+    for i in 0..8 {
+        let offset = VERTICES[i];
+        let (x, y, z) = (x + offset.0, y + offset.1, z + offset.2);
+        let point_value = voxel_grid.read(x, y, z);
+
+        // if value at pos is below the isovalue => asign 1 to the corresponding bit location
+        cube_idx |= ((point_value < isovalue) as u8) << i;
+    }
+
+    TRIANGULATIONS[cube_idx as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::map_generator::{
+        endless_terrain::CHUNK_SIZE, sphere_noise::SphereNoiseDensity, MapGenerator,
+    };
+
+    /// Density of a sphere lying entirely inside of chunk [0 0 0]
+    fn sphere_grid(radius: f32, center: Vec3) -> VoxelGrid {
+        MapGenerator::new(SphereNoiseDensity::new(radius).with_center(center)).generate_noise_lod(
+            IVec3::ZERO,
+            CHUNK_SIZE as usize,
+            0,
+            0,
+        )
+    }
+
+    #[test]
+    fn sphere_vertices_lie_on_its_surface() {
+        let (radius, center) = (5.5, Vec3::new(8.0, 7.7, 8.3));
+        let mesh_data =
+            ChunkMesher::new(0.0, NormalMode::AveragedFace).mesh(&sphere_grid(radius, center));
+
+        assert!(!mesh_data.positions.is_empty());
+        for pos in &mesh_data.positions {
+            let error = (pos.distance(center) - radius).abs();
+            assert!(error < 0.05, "Vertex {pos} is {error} away from the sphere");
+        }
+    }
+
+    #[test]
+    fn closed_mesh_is_watertight() {
+        let mesh_data = ChunkMesher::new(0.0, NormalMode::AveragedFace)
+            .mesh(&sphere_grid(5.5, Vec3::new(8.0, 7.7, 8.3)));
+
+        // Vertices are shared between the triangles touching them
+        assert!(mesh_data.positions.len() < mesh_data.indices.len());
+
+        let mut edges: HashMap<(u32, u32), usize> = HashMap::new();
+        for tri in mesh_data.indices.chunks_exact(3) {
+            for (a, b) in [(tri[0], tri[1]), (tri[1], tri[2]), (tri[2], tri[0])] {
+                *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+            }
+        }
+        for (edge, count) in edges {
+            assert_eq!(count, 2, "Edge {edge:?} belongs to {count} triangles");
+        }
+    }
+
+    #[test]
+    fn neighbouring_chunk_meshes_meet_on_their_border() {
+        // Crosses the border between both chunks
+        let sphere = SphereNoiseDensity::new(6.0).with_center(Vec3::new(16.0, 8.0, 8.0));
+        let map_gen = MapGenerator::new(sphere);
+        let size = CHUNK_SIZE as usize;
+        let mesher = ChunkMesher::new(0.0, NormalMode::Gradient);
+
+        let mesh = |chunk_coord: IVec3| {
+            let voxel_grid = map_gen.generate_noise_lod(chunk_coord, size, 0, 1);
+            let mesh_data = mesher.mesh(&voxel_grid);
+            let origin = voxel_grid.origin();
+            mesh_data
+                .positions
+                .iter()
+                .map(|&pos| origin + pos)
+                .zip(mesh_data.normals)
+                .collect::<Vec<_>>()
+        };
+        let (a, b) = (mesh(IVec3::new(0, 0, 0)), mesh(IVec3::new(1, 0, 0)));
+
+        let border = CHUNK_SIZE as f32;
+        let a_border: Vec<_> = a.iter().filter(|(pos, _)| pos.x == border).collect();
+        assert!(!a_border.is_empty());
+        for (pos, normal) in a_border {
+            // The apron gives both chunks the same gradients on their border
+            assert!(
+                b.iter()
+                    .any(|(b_pos, b_normal)| b_pos == pos && b_normal == normal),
+                "Vertex {pos} has no match in the neighbouring chunk"
+            );
+        }
+    }
+}
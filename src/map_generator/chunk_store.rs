@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use super::{
+    noise_generator::VoxelGrid,
+    terrain_edit::{apply_brush, TerrainEditEvent},
+};
+
+/// Terrain which differs from what the generator produces, kept by chunk.
+///
+/// It survives chunks being unloaded and is applied over the generated density whenever a chunk
+/// is generated again. Chunks which were never edited are not stored at all so they stay
+/// procedural.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct ChunkStore {
+    chunks: HashMap<IVec3, StoredChunk>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct StoredChunk {
+    /// Full resolution density loaded from a save, replacing the generated one
+    pub voxel_grid: Option<VoxelGrid>,
+    /// Edits made since, kept as deltas so they can be replayed at any level of detail
+    pub edits: Vec<TerrainEditEvent>,
+}
+
+impl StoredChunk {
+    /// Apply the stored density and edits over a freshly generated grid
+    pub fn apply(&self, voxel_grid: &mut VoxelGrid, isovalue: f32) {
+        if let Some(stored_grid) = &self.voxel_grid {
+            voxel_grid.overlay(stored_grid);
+        }
+        for edit in &self.edits {
+            apply_brush(voxel_grid, edit, isovalue);
+        }
+    }
+}
+
+impl ChunkStore {
+    pub fn record(&mut self, chunk_coord: IVec3, edit: TerrainEditEvent) {
+        self.chunks.entry(chunk_coord).or_default().edits.push(edit);
+    }
+
+    /// Store density loaded from a save. Edits already made to that chunk are kept and applied
+    /// over it.
+    pub fn insert_voxel_grid(&mut self, chunk_coord: IVec3, voxel_grid: VoxelGrid) {
+        self.chunks.entry(chunk_coord).or_default().voxel_grid = Some(voxel_grid);
+    }
+
+    pub fn get(&self, chunk_coord: IVec3) -> Option<&StoredChunk> {
+        self.chunks.get(&chunk_coord)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&IVec3, &StoredChunk)> {
+        self.chunks.iter()
+    }
+}
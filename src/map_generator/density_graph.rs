@@ -0,0 +1,209 @@
+//! Composable nodes for building density functions out of smaller ones.
+//!
+//! Every node is a `NoiseGenerator` and takes its inputs as `Box<dyn NoiseGenerator>`, so trees of
+//! nodes can be nested freely and passed to `MapGenerator::new`. Densities are negative inside
+//! solid terrain and positive in air, which makes `Min` a union and `Max` an intersection.
+
+use bevy::math::{Quat, Vec3};
+use fastnoise_lite::FastNoiseLite;
+
+use super::{terrain_noise::fbm, NoiseGenerator};
+
+pub type DensityNode = Box<dyn NoiseGenerator>;
+
+impl NoiseGenerator for DensityNode {
+    fn get_scalar(&self, x: f32, y: f32, z: f32) -> f32 {
+        self.as_ref().get_scalar(x, y, z)
+    }
+}
+
+/// Same value everywhere
+pub struct Constant(pub f32);
+
+impl NoiseGenerator for Constant {
+    fn get_scalar(&self, _x: f32, _y: f32, _z: f32) -> f32 {
+        self.0
+    }
+}
+
+/// The height (y coordinate) of the sample, a flat ground at y = 0
+pub struct Height;
+
+impl NoiseGenerator for Height {
+    fn get_scalar(&self, _x: f32, y: f32, _z: f32) -> f32 {
+        y
+    }
+}
+
+/// Fractal noise in the -1..1 range
+pub struct NoiseSource {
+    noise: FastNoiseLite,
+    octaves: u32,
+    persistance: f32,
+    lacunarity: f32,
+    /// Only sample the x and z axis
+    planar: bool,
+}
+
+impl NoiseSource {
+    pub fn new(seed: i32, frequency: f32, octaves: u32, persistance: f32, lacunarity: f32) -> Self {
+        let mut noise = FastNoiseLite::new();
+        noise.set_noise_type(Some(fastnoise_lite::NoiseType::Perlin));
+        noise.set_seed(Some(seed));
+        noise.set_frequency(Some(frequency));
+
+        Self {
+            noise,
+            octaves,
+            persistance,
+            lacunarity,
+            planar: false,
+        }
+    }
+
+    /// Sample the noise in 2D on the xz plane, ignoring the height
+    pub fn planar(mut self) -> Self {
+        self.planar = true;
+        self
+    }
+}
+
+impl NoiseGenerator for NoiseSource {
+    fn get_scalar(&self, x: f32, y: f32, z: f32) -> f32 {
+        fbm(self.octaves, self.persistance, self.lacunarity, |freq| {
+            if self.planar {
+                self.noise.get_noise_2d(x * freq, z * freq)
+            } else {
+                self.noise.get_noise_3d(x * freq, y * freq, z * freq)
+            }
+        })
+    }
+}
+
+/// Sum of 2 densities
+pub struct Add(pub DensityNode, pub DensityNode);
+
+impl NoiseGenerator for Add {
+    fn get_scalar(&self, x: f32, y: f32, z: f32) -> f32 {
+        self.0.get_scalar(x, y, z) + self.1.get_scalar(x, y, z)
+    }
+}
+
+/// Product of 2 densities
+pub struct Multiply(pub DensityNode, pub DensityNode);
+
+impl NoiseGenerator for Multiply {
+    fn get_scalar(&self, x: f32, y: f32, z: f32) -> f32 {
+        self.0.get_scalar(x, y, z) * self.1.get_scalar(x, y, z)
+    }
+}
+
+/// Smallest of 2 densities, the union of both shapes
+pub struct Min(pub DensityNode, pub DensityNode);
+
+impl NoiseGenerator for Min {
+    fn get_scalar(&self, x: f32, y: f32, z: f32) -> f32 {
+        self.0.get_scalar(x, y, z).min(self.1.get_scalar(x, y, z))
+    }
+}
+
+/// Biggest of 2 densities, the intersection of both shapes
+pub struct Max(pub DensityNode, pub DensityNode);
+
+impl NoiseGenerator for Max {
+    fn get_scalar(&self, x: f32, y: f32, z: f32) -> f32 {
+        self.0.get_scalar(x, y, z).max(self.1.get_scalar(x, y, z))
+    }
+}
+
+/// The first shape with the second one carved out of it
+pub struct Subtract(pub DensityNode, pub DensityNode);
+
+impl NoiseGenerator for Subtract {
+    fn get_scalar(&self, x: f32, y: f32, z: f32) -> f32 {
+        self.0.get_scalar(x, y, z).max(-self.1.get_scalar(x, y, z))
+    }
+}
+
+/// Union of 2 shapes blended over a distance of `k`
+pub struct SmoothMin {
+    pub a: DensityNode,
+    pub b: DensityNode,
+    pub k: f32,
+}
+
+impl NoiseGenerator for SmoothMin {
+    fn get_scalar(&self, x: f32, y: f32, z: f32) -> f32 {
+        let (a, b) = (self.a.get_scalar(x, y, z), self.b.get_scalar(x, y, z));
+        if self.k <= 0.0 {
+            return a.min(b);
+        }
+
+        // Polynomial smooth minimum
+        let h = (0.5 + 0.5 * (b - a) / self.k).clamp(0.0, 1.0);
+        b + (a - b) * h - self.k * h * (1.0 - h)
+    }
+}
+
+pub struct Clamp {
+    pub source: DensityNode,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl NoiseGenerator for Clamp {
+    fn get_scalar(&self, x: f32, y: f32, z: f32) -> f32 {
+        self.source.get_scalar(x, y, z).clamp(self.min, self.max)
+    }
+}
+
+/// Linearly map the `from` range of the source onto the `to` range
+pub struct Remap {
+    pub source: DensityNode,
+    pub from: (f32, f32),
+    pub to: (f32, f32),
+}
+
+impl NoiseGenerator for Remap {
+    fn get_scalar(&self, x: f32, y: f32, z: f32) -> f32 {
+        let t = (self.source.get_scalar(x, y, z) - self.from.0) / (self.from.1 - self.from.0);
+        self.to.0 + t * (self.to.1 - self.to.0)
+    }
+}
+
+/// Move the source by `offset`
+pub struct Translate {
+    pub source: DensityNode,
+    pub offset: Vec3,
+}
+
+impl NoiseGenerator for Translate {
+    fn get_scalar(&self, x: f32, y: f32, z: f32) -> f32 {
+        self.source.get_scalar_v(Vec3::new(x, y, z) - self.offset)
+    }
+}
+
+/// Stretch the source by `scale` on each axis
+pub struct Scale {
+    pub source: DensityNode,
+    pub scale: Vec3,
+}
+
+impl NoiseGenerator for Scale {
+    fn get_scalar(&self, x: f32, y: f32, z: f32) -> f32 {
+        self.source.get_scalar_v(Vec3::new(x, y, z) / self.scale)
+    }
+}
+
+/// Rotate the source around the origin
+pub struct Rotate {
+    pub source: DensityNode,
+    pub rotation: Quat,
+}
+
+impl NoiseGenerator for Rotate {
+    fn get_scalar(&self, x: f32, y: f32, z: f32) -> f32 {
+        self.source
+            .get_scalar_v(self.rotation.inverse() * Vec3::new(x, y, z))
+    }
+}
@@ -4,7 +4,12 @@ use bevy::{prelude::*, utils::warn};
 
 use crate::{player::Player, settings::render::RenderSettings};
 
-use super::map_display::RenderChunk;
+use super::{
+    lod::LodNeighbours,
+    map_display::{ComputeChunkMesh, RenderChunk},
+    noise_generator::VoxelGrid,
+    MapGenerator,
+};
 
 pub const CHUNK_SIZE: u8 = 16;
 
@@ -12,14 +17,36 @@ pub struct EndlessTerrainPlugin;
 
 impl Plugin for EndlessTerrainPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<ChunkMap>()
-            .add_systems(Update, (update_visible_chunks, update_chunk));
+        app.init_resource::<ChunkMap>().add_systems(
+            Update,
+            (
+                // Chunks can't be generated before the generator is loaded
+                update_visible_chunks.run_if(resource_exists::<MapGenerator>),
+                update_chunk_lods.run_if(resource_exists::<MapGenerator>),
+                update_chunk,
+                unload_hidden_chunks,
+            )
+                .chain(),
+        );
     }
 }
 
 #[derive(Debug, Default, Resource)]
 pub struct ChunkMap(pub HashMap<IVec3, Chunk>);
 
+impl ChunkMap {
+    /// Density at a world-space position, `None` if its chunk has not been generated yet
+    pub fn density_at(&self, pos: Vec3) -> Option<f32> {
+        let chunk_coord = (pos / CHUNK_SIZE as f32).floor().as_ivec3();
+        let voxel_grid = self.0.get(&chunk_coord)?.voxel_grid.as_ref()?;
+        Some(voxel_grid.interpolate(pos - voxel_grid.origin()))
+    }
+}
+
+/// Coordinate of the chunk an entity renders
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkCoord(pub IVec3);
+
 fn update_visible_chunks(
     mut commands: Commands,
     mut chunk_map: ResMut<ChunkMap>,
@@ -38,6 +65,7 @@ fn update_visible_chunks(
     let curr_chunk_coord_x = (player_coord.x / CHUNK_SIZE as f32).floor() as i32;
     let curr_chunk_coord_y = (player_coord.y / CHUNK_SIZE as f32).floor() as i32;
     let curr_chunk_coord_z = (player_coord.z / CHUNK_SIZE as f32).floor() as i32;
+    let player_chunk_coord = IVec3::new(curr_chunk_coord_x, curr_chunk_coord_y, curr_chunk_coord_z);
 
     // Loop through all chunks in render_distance
     // and add them to chunk_map
@@ -53,8 +81,9 @@ fn update_visible_chunks(
                 );
 
                 if let Vacant(e) = chunk_map.0.entry(viewed_chunk_coord) {
-                    e.insert(Chunk::new());
-                    // commands.add(RenderChunk::new(viewed_chunk_coord));
+                    let lods = chunk_lods(&render_cfg, player_chunk_coord, viewed_chunk_coord);
+                    e.insert(Chunk::new(lods));
+                    commands.add(RenderChunk::new(viewed_chunk_coord));
                 }
             }
         }
@@ -64,11 +93,105 @@ fn update_visible_chunks(
 #[derive(Debug)]
 pub struct Chunk {
     pub visible: bool,
+    pub state: ChunkState,
+    /// Entity holding the chunk's mesh, spawned once the chunk is meshed. Chunks with nothing to
+    /// render have none.
+    pub entity: Option<Entity>,
+    /// Generation in progress, replacing the mesh and density once finished
+    pub task: Option<ComputeChunkMesh>,
+    /// Set once the generation task has finished
+    pub mesh: Option<Handle<Mesh>>,
+    /// Density the mesh was built from, edited in place by brushes while it is raw
+    pub voxel_grid: Option<VoxelGrid>,
+    /// Level of detail the chunk is meshed with, along with the one of its neighbours
+    pub lods: LodNeighbours,
 }
 
 impl Chunk {
-    pub fn new() -> Self {
-        Self { visible: false }
+    pub fn new(lods: LodNeighbours) -> Self {
+        Self {
+            visible: false,
+            state: ChunkState::default(),
+            entity: None,
+            task: None,
+            mesh: None,
+            voxel_grid: None,
+            lods,
+        }
+    }
+}
+
+/// What the generation of a chunk found
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkState {
+    #[default]
+    Generating,
+    /// The surface goes through the chunk
+    Meshed,
+    /// Entirely above the surface, neither meshed nor spawned
+    Empty,
+    /// Entirely below the surface, neither meshed nor spawned
+    Solid,
+}
+
+impl ChunkState {
+    /// State of a chunk generated with the density of `voxel_grid`
+    pub fn of(voxel_grid: &VoxelGrid, isovalue: f32) -> Self {
+        let (_, max) = voxel_grid.bounds();
+        if voxel_grid.straddles(isovalue) {
+            ChunkState::Meshed
+        } else if max < isovalue {
+            ChunkState::Solid
+        } else {
+            ChunkState::Empty
+        }
+    }
+
+    /// Whether the chunk was skipped for having nothing to render
+    pub fn is_skipped(&self) -> bool {
+        matches!(self, ChunkState::Empty | ChunkState::Solid)
+    }
+}
+
+/// Levels of detail of the chunk at `chunk_coord` and its neighbours, seen from `player_chunk_coord`
+fn chunk_lods(
+    render_cfg: &RenderSettings,
+    player_chunk_coord: IVec3,
+    chunk_coord: IVec3,
+) -> LodNeighbours {
+    LodNeighbours::new(chunk_coord, |coord| {
+        let distance = (coord - player_chunk_coord).abs().max_element() as u32;
+        render_cfg.lod_level(distance)
+    })
+}
+
+/// Remesh the chunks whose level of detail (or the one of a neighbour) changed since the player
+/// moved to another chunk
+fn update_chunk_lods(
+    mut commands: Commands,
+    mut chunk_map: ResMut<ChunkMap>,
+    mut last_player_chunk: Local<Option<IVec3>>,
+    render_cfg: Res<RenderSettings>,
+    player_pos_q: Query<&Transform, With<Player>>,
+) {
+    let Ok(player_t) = player_pos_q.get_single() else {
+        return;
+    };
+
+    let player_chunk_coord = (player_t.translation / CHUNK_SIZE as f32)
+        .floor()
+        .as_ivec3();
+    if *last_player_chunk == Some(player_chunk_coord) && !render_cfg.is_changed() {
+        return;
+    }
+    *last_player_chunk = Some(player_chunk_coord);
+
+    for (chunk_coord, chunk) in chunk_map.0.iter_mut() {
+        let lods = chunk_lods(&render_cfg, player_chunk_coord, *chunk_coord);
+        if lods != chunk.lods {
+            chunk.lods = lods;
+            commands.add(RenderChunk::new(*chunk_coord));
+        }
     }
 }
 
@@ -94,3 +217,16 @@ pub(super) fn update_chunk(
         chunk.visible = distance_xz <= render_distance_xz && distance.y <= render_distance_y;
     }
 }
+
+/// Despawn chunks that left the render distance.
+///
+/// Dropping their `Chunk` also cancels their generation task if any, and frees their mesh once its
+/// last handle is dropped.
+pub(super) fn unload_hidden_chunks(mut commands: Commands, mut chunk_map: ResMut<ChunkMap>) {
+    chunk_map.0.retain(|_, chunk| {
+        if let (false, Some(entity)) = (chunk.visible, chunk.entity) {
+            commands.entity(entity).despawn_recursive();
+        }
+        chunk.visible
+    });
+}
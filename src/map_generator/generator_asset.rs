@@ -0,0 +1,245 @@
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{
+    density_graph::{
+        Add, Clamp, Constant, DensityNode, Height, Max, Min, Multiply, NoiseSource, Remap, Rotate,
+        Scale, SmoothMin, Subtract, Translate,
+    },
+    sphere_noise::SphereNoiseDensity,
+    terrain_noise::TerrainNoiseDensity,
+    voxel_material::MaterialRules,
+    MapGenerator,
+};
+
+/// Description of a terrain generator, loaded from a `.terrain.ron` file
+#[derive(Asset, TypePath, Debug, Deserialize, Serialize)]
+pub struct TerrainGeneratorDef {
+    #[serde(default)]
+    pub isovalue: f32,
+    pub density: DensityDef,
+    #[serde(default)]
+    pub materials: MaterialRules,
+}
+
+impl TerrainGeneratorDef {
+    /// Generator used when no definition could be loaded, the same as
+    /// `TerrainNoiseDensity::default`
+    pub fn fallback() -> Self {
+        Self {
+            isovalue: 0.0,
+            density: DensityDef::Terrain {
+                seed: 6969,
+                frequency: 0.01,
+                octaves: 4,
+                persistance: 0.5,
+                lacunarity: 2.0,
+                base_height: 0.0,
+                height_amplitude: 24.0,
+                density_amplitude: 12.0,
+            },
+            materials: MaterialRules::default(),
+        }
+    }
+
+    pub fn to_map_generator(&self) -> MapGenerator {
+        MapGenerator::new(self.density.build())
+            .with_isovalue(self.isovalue)
+            .with_materials(self.materials.clone())
+    }
+}
+
+/// Serialized form of a tree of `density_graph` nodes
+#[derive(Debug, Deserialize, Serialize)]
+pub enum DensityDef {
+    Constant(f32),
+    Height,
+    Noise {
+        seed: i32,
+        frequency: f32,
+        octaves: u32,
+        persistance: f32,
+        lacunarity: f32,
+        #[serde(default)]
+        planar: bool,
+    },
+    Sphere {
+        radius: f32,
+        center: [f32; 3],
+    },
+    Terrain {
+        seed: i32,
+        frequency: f32,
+        octaves: u32,
+        persistance: f32,
+        lacunarity: f32,
+        base_height: f32,
+        height_amplitude: f32,
+        density_amplitude: f32,
+    },
+    Add(Box<DensityDef>, Box<DensityDef>),
+    Multiply(Box<DensityDef>, Box<DensityDef>),
+    Min(Box<DensityDef>, Box<DensityDef>),
+    Max(Box<DensityDef>, Box<DensityDef>),
+    Subtract(Box<DensityDef>, Box<DensityDef>),
+    SmoothMin {
+        a: Box<DensityDef>,
+        b: Box<DensityDef>,
+        k: f32,
+    },
+    Clamp {
+        source: Box<DensityDef>,
+        min: f32,
+        max: f32,
+    },
+    Remap {
+        source: Box<DensityDef>,
+        from: (f32, f32),
+        to: (f32, f32),
+    },
+    Translate {
+        source: Box<DensityDef>,
+        offset: [f32; 3],
+    },
+    Scale {
+        source: Box<DensityDef>,
+        scale: [f32; 3],
+    },
+    Rotate {
+        source: Box<DensityDef>,
+        axis: [f32; 3],
+        /// Angle in degrees
+        angle: f32,
+    },
+}
+
+impl DensityDef {
+    pub fn build(&self) -> DensityNode {
+        match self {
+            DensityDef::Constant(value) => Box::new(Constant(*value)),
+            DensityDef::Height => Box::new(Height),
+            DensityDef::Noise {
+                seed,
+                frequency,
+                octaves,
+                persistance,
+                lacunarity,
+                planar,
+            } => {
+                let noise =
+                    NoiseSource::new(*seed, *frequency, *octaves, *persistance, *lacunarity);
+                match planar {
+                    true => Box::new(noise.planar()),
+                    false => Box::new(noise),
+                }
+            }
+            DensityDef::Sphere { radius, center } => {
+                Box::new(SphereNoiseDensity::new(*radius).with_center(Vec3::from_array(*center)))
+            }
+            DensityDef::Terrain {
+                seed,
+                frequency,
+                octaves,
+                persistance,
+                lacunarity,
+                base_height,
+                height_amplitude,
+                density_amplitude,
+            } => Box::new(
+                TerrainNoiseDensity::new(*seed, *frequency, *octaves, *persistance, *lacunarity)
+                    .with_heights(*base_height, *height_amplitude, *density_amplitude),
+            ),
+            DensityDef::Add(a, b) => Box::new(Add(a.build(), b.build())),
+            DensityDef::Multiply(a, b) => Box::new(Multiply(a.build(), b.build())),
+            DensityDef::Min(a, b) => Box::new(Min(a.build(), b.build())),
+            DensityDef::Max(a, b) => Box::new(Max(a.build(), b.build())),
+            DensityDef::Subtract(a, b) => Box::new(Subtract(a.build(), b.build())),
+            DensityDef::SmoothMin { a, b, k } => Box::new(SmoothMin {
+                a: a.build(),
+                b: b.build(),
+                k: *k,
+            }),
+            DensityDef::Clamp { source, min, max } => Box::new(Clamp {
+                source: source.build(),
+                min: *min,
+                max: *max,
+            }),
+            DensityDef::Remap { source, from, to } => Box::new(Remap {
+                source: source.build(),
+                from: *from,
+                to: *to,
+            }),
+            DensityDef::Translate { source, offset } => Box::new(Translate {
+                source: source.build(),
+                offset: Vec3::from_array(*offset),
+            }),
+            DensityDef::Scale { source, scale } => Box::new(Scale {
+                source: source.build(),
+                scale: Vec3::from_array(*scale),
+            }),
+            DensityDef::Rotate {
+                source,
+                axis,
+                angle,
+            } => Box::new(Rotate {
+                source: source.build(),
+                rotation: Quat::from_axis_angle(
+                    Vec3::from_array(*axis).try_normalize().unwrap_or(Vec3::Y),
+                    angle.to_radians(),
+                ),
+            }),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct TerrainGeneratorLoader;
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum TerrainGeneratorLoaderError {
+    #[error("Could not read terrain generator: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not parse terrain generator: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+}
+
+impl AssetLoader for TerrainGeneratorLoader {
+    type Asset = TerrainGeneratorDef;
+    type Settings = ();
+    type Error = TerrainGeneratorLoaderError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a Self::Settings,
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["terrain.ron"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_generator_parses() {
+        let bytes = include_bytes!("../../assets/terrain/default.terrain.ron");
+        // Parsed the same way as `TerrainGeneratorLoader`
+        let generator_def: TerrainGeneratorDef =
+            ron::de::from_bytes(bytes).expect("Shipped terrain generator should parse");
+        generator_def.to_map_generator();
+    }
+}
@@ -0,0 +1,134 @@
+use bevy::math::IVec3;
+
+use super::noise_generator::VoxelGrid;
+
+/// Level of detail of a chunk and of its 26 neighbours.
+///
+/// A chunk at level `n` is sampled every `2^n` voxels. Samples on the border of a chunk are shared
+/// with its neighbours, so meshing a chunk needs to know the level of each of them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LodNeighbours([[[u8; 3]; 3]; 3]);
+
+impl LodNeighbours {
+    pub fn new(chunk_coord: IVec3, lod_of: impl Fn(IVec3) -> u8) -> Self {
+        let mut levels = [[[0; 3]; 3]; 3];
+        for (x, plane) in levels.iter_mut().enumerate() {
+            for (y, row) in plane.iter_mut().enumerate() {
+                for (z, level) in row.iter_mut().enumerate() {
+                    let dir = IVec3::new(x as i32, y as i32, z as i32) - IVec3::ONE;
+                    *level = lod_of(chunk_coord + dir);
+                }
+            }
+        }
+
+        Self(levels)
+    }
+
+    /// Level of detail of the chunk itself
+    pub fn level(&self) -> u8 {
+        self.get(IVec3::ZERO)
+    }
+
+    /// Level of detail of the neighbour in `dir`, each axis being in -1..=1
+    pub fn get(&self, dir: IVec3) -> u8 {
+        let idx = (dir + IVec3::ONE).as_uvec3();
+        self.0[idx.x as usize][idx.y as usize][idx.z as usize]
+    }
+
+    /// Coarsest level among the chunks sharing the grid point `pos` of a grid of `size` samples
+    fn shared_level(&self, pos: [usize; 3], size: usize) -> u8 {
+        let axis_dirs = |idx: usize| match idx {
+            0 => -1..=0,
+            _ if idx == size - 1 => 0..=1,
+            _ => 0..=0,
+        };
+
+        let mut level = self.level();
+        for x in axis_dirs(pos[0]) {
+            for y in axis_dirs(pos[1]) {
+                for z in axis_dirs(pos[2]) {
+                    level = level.max(self.get(IVec3::new(x, y, z)));
+                }
+            }
+        }
+
+        level
+    }
+
+    /// Ratio between the voxel spacing of the neighbour in `dir` and the one of the chunk, clamped
+    /// to the number of cells of a grid of `size` samples. 1 when the neighbour isn't coarser.
+    pub fn ratio(&self, dir: IVec3, size: usize) -> usize {
+        let level = self.level();
+        (1usize << self.get(dir).saturating_sub(level)).min(size - 1)
+    }
+}
+
+/// Replace the border samples shared with coarser neighbours by values interpolated from the
+/// coarser lattice.
+///
+/// Both sides of a border then agree on the density along every coarse edge, so surface crossings
+/// on the border are placed at the same positions by both chunks.
+pub fn snap_to_coarser_neighbours(voxel_grid: &mut VoxelGrid, lods: &LodNeighbours) {
+    let size = voxel_grid.size;
+    let last = size - 1;
+    let level = lods.level();
+
+    // Edges go first, faces are then interpolated from the snapped edges. Corners are part of every
+    // lattice so they never change.
+    for on_borders in [2, 1] {
+        for z in 0..size {
+            for y in 0..size {
+                for x in 0..size {
+                    let pos = [x, y, z];
+                    let borders = pos.iter().filter(|&&idx| idx == 0 || idx == last).count();
+                    if borders != on_borders {
+                        continue;
+                    }
+
+                    let shared_level = lods.shared_level(pos, size);
+                    if shared_level <= level {
+                        continue;
+                    }
+
+                    let ratio = (1usize << (shared_level - level)).min(last);
+                    let value = interpolate_lattice(voxel_grid, pos, ratio);
+                    voxel_grid.write(x, y, z, value);
+                }
+            }
+        }
+    }
+}
+
+/// Multilinear interpolation of the lattice made of every `ratio`th sample of the grid
+fn interpolate_lattice(voxel_grid: &VoxelGrid, pos: [usize; 3], ratio: usize) -> f32 {
+    let last = voxel_grid.size - 1;
+
+    // Lower lattice point and interpolation factor on each axis
+    let axes = pos.map(|idx| {
+        let lo = (idx / ratio) * ratio;
+        let hi = (lo + ratio).min(last);
+        let t = if hi > lo {
+            (idx - lo) as f32 / (hi - lo) as f32
+        } else {
+            0.0
+        };
+        (lo, hi, t)
+    });
+
+    let mut value = 0.0;
+    for corner in 0..8 {
+        let mut weight = 1.0;
+        let mut point = [0; 3];
+        for (axis, &(lo, hi, t)) in axes.iter().enumerate() {
+            let upper = (corner >> axis) & 1 == 1;
+            weight *= if upper { t } else { 1.0 - t };
+            point[axis] = if upper { hi } else { lo };
+        }
+
+        if weight > 0.0 {
+            value += weight * voxel_grid.read(point[0], point[1], point[2]);
+        }
+    }
+
+    value
+}
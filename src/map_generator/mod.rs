@@ -1,86 +1,201 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, sync::Arc};
 
-use bevy::prelude::*;
-use endless_terrain::{EndlessTerrainPlugin, CHUNK_SIZE};
+use bevy::{asset::AssetLoadFailedEvent, prelude::*};
+#[cfg(feature = "physics")]
+use chunk_collider::ChunkColliderPlugin;
+use endless_terrain::{unload_hidden_chunks, ChunkMap, EndlessTerrainPlugin, CHUNK_SIZE};
 use fastnoise_lite::FastNoiseLite;
-use map_display::RenderChunk;
+use generator_asset::{TerrainGeneratorDef, TerrainGeneratorLoader};
+use map_display::{poll_chunk_meshes, RenderChunk};
 use noise_generator::{Noise, VoxelGrid};
-use sphere_noise::SphereNoiseDensity;
+use terrain_edit::TerrainEditPlugin;
+use terrain_material::TerrainMaterialPlugin;
+use voxel_material::MaterialRules;
+use world_save::WorldSavePlugin;
 
 use crate::utils::To1DIndex;
 
+#[cfg(feature = "physics")]
+mod chunk_collider;
+mod chunk_mesher;
+pub mod chunk_store;
+pub mod density_graph;
 pub mod endless_terrain;
+mod generator_asset;
+pub mod lod;
 mod map_display;
 mod marching_table;
 mod noise_generator;
+pub mod region;
 mod sphere_noise;
+pub mod terrain_edit;
+pub mod terrain_material;
+mod terrain_noise;
+pub mod terrain_raycast;
+pub mod voxel_material;
+pub mod voxel_storage;
+pub mod world_save;
 
 pub struct MapGeneratorPlugin;
 
 impl Plugin for MapGeneratorPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(EndlessTerrainPlugin)
-            .add_systems(Startup, ready);
+        app.add_plugins((
+            EndlessTerrainPlugin,
+            TerrainEditPlugin,
+            TerrainMaterialPlugin,
+            WorldSavePlugin,
+        ))
+        .init_asset::<TerrainGeneratorDef>()
+        .init_asset_loader::<TerrainGeneratorLoader>()
+        .add_systems(Startup, ready)
+        .add_systems(
+            Update,
+            (
+                apply_terrain_generator,
+                // Finished chunks are still in the `ChunkMap` before hidden ones are unloaded
+                poll_chunk_meshes.before(unload_hidden_chunks),
+            ),
+        );
+
+        #[cfg(feature = "physics")]
+        app.add_plugins(ChunkColliderPlugin);
     }
 }
 
-fn ready(
+/// Terrain generator definition the `MapGenerator` is built from
+#[derive(Resource)]
+struct TerrainGeneratorHandle(Handle<TerrainGeneratorDef>);
+
+fn ready(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let generator = asset_server.load("terrain/default.terrain.ron");
+    commands.insert_resource(TerrainGeneratorHandle(generator));
+}
+
+/// (Re)build the `MapGenerator` whenever the terrain generator definition is loaded or edited, and
+/// regenerate the chunks that were already loaded with it
+fn apply_terrain_generator(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut asset_events: EventReader<AssetEvent<TerrainGeneratorDef>>,
+    mut failed_events: EventReader<AssetLoadFailedEvent<TerrainGeneratorDef>>,
+    generator_defs: Res<Assets<TerrainGeneratorDef>>,
+    generator_handle: Res<TerrainGeneratorHandle>,
+    chunk_map: Res<ChunkMap>,
+    map_gen: Option<Res<MapGenerator>>,
 ) {
-    // let map_gen = MapGenerator::new(NoiseDensity::default());
-    let map_gen = MapGenerator::new(SphereNoiseDensity::new(7.0));
-    commands.insert_resource(map_gen);
-
-    commands.add(RenderChunk::new(IVec3::new(0, 0, 0)));
-    // commands.add(RenderChunk::new(IVec3::new(-1, 0, 0)));
-    // commands.add(RenderChunk::new(IVec3::new(0, 0, 0)));
-    // commands.add(RenderChunk::new(IVec3::new(1, 0, 0)));
-    // commands.add(RenderChunk::new(IVec3::new(-1, 0, 1)));
-    // commands.add(RenderChunk::new(IVec3::new(0, 0, 1)));
-    // commands.add(RenderChunk::new(IVec3::new(1, 0, 1)));
-    // commands.add(RenderChunk::new(IVec3::new(-1, 0, -1)));
-    // commands.add(RenderChunk::new(IVec3::new(0, 0, -1)));
-    // commands.add(RenderChunk::new(IVec3::new(1, 0, -1)));
+    for event in failed_events.read() {
+        // A failed reload keeps the generator the loaded chunks were generated with
+        if map_gen.is_some() {
+            error!(
+                "Could not reload terrain generator '{}', keeping the current one: {}",
+                event.path, event.error
+            );
+            continue;
+        }
+
+        error!(
+            "Could not load terrain generator '{}', falling back to the default one: {}",
+            event.path, event.error
+        );
+        commands.insert_resource(TerrainGeneratorDef::fallback().to_map_generator());
+    }
+
+    let mut changed = false;
+    for event in asset_events.read() {
+        changed |= event.is_loaded_with_dependencies(&generator_handle.0)
+            || event.is_modified(&generator_handle.0);
+    }
+
+    if !changed {
+        return;
+    }
+
+    let Some(generator_def) = generator_defs.get(&generator_handle.0) else {
+        return;
+    };
+
+    commands.insert_resource(generator_def.to_map_generator());
+    for chunk_coord in chunk_map.0.keys() {
+        commands.add(RenderChunk::new(*chunk_coord));
+    }
 }
 
-#[derive(Resource)]
+/// Cheap to clone, the generator is shared with the chunk generation tasks
+#[derive(Resource, Clone)]
 pub struct MapGenerator {
-    generation_type: Box<dyn NoiseGenerator>,
+    generation_type: Arc<dyn NoiseGenerator>,
+    /// Scalar value at which the surface is extracted. Samples below it are
+    /// considered solid, samples at or above it are air.
+    isovalue: f32,
+    materials: Arc<MaterialRules>,
 }
 
 impl MapGenerator {
     pub fn new(gen_type: impl NoiseGenerator + 'static) -> Self {
         Self {
-            generation_type: Box::new(gen_type),
+            generation_type: Arc::new(gen_type),
+            isovalue: 0.0,
+            materials: Arc::default(),
         }
     }
 
-    pub fn generate_noise(&self, chunk_coord: IVec3, size: usize) -> VoxelGrid {
+    pub fn with_isovalue(mut self, isovalue: f32) -> Self {
+        self.isovalue = isovalue;
+        self
+    }
+
+    pub fn with_materials(mut self, materials: MaterialRules) -> Self {
+        self.materials = Arc::new(materials);
+        self
+    }
+
+    pub fn isovalue(&self) -> f32 {
+        self.isovalue
+    }
+
+    pub fn generator(&self) -> &dyn NoiseGenerator {
+        self.generation_type.as_ref()
+    }
+
+    /// Sample a chunk of `size` voxels per side every `2^lod` voxels, padded by `apron` samples
+    /// taken from the neighbouring chunks on each side
+    pub fn generate_noise_lod(
+        &self,
+        chunk_coord: IVec3,
+        size: usize,
+        lod: u8,
+        apron: usize,
+    ) -> VoxelGrid {
+        let step = 1usize << lod;
+
         // Grid size (VoxelGrid size) is increased because as opposed to the chunk size which is correctly 16^3 in
         // size. Block data however start from 0 to 16, included in all of the corners of the
         // grid/chunk.
-        let grid_size = size + 1;
-        let mut noise_map: VoxelGrid = VoxelGrid::new(grid_size, chunk_coord);
-
-        for z in 0..grid_size {
-            for y in 0..grid_size {
-                for x in 0..grid_size {
-                    // let offset = (chunk_coord * size as i32).as_vec3();
-                    // let x = x as f32 + offset.x;
-                    // let y = y as f32 + offset.y;
-                    // let z = z as f32 + offset.z;
-
-                    let x = x as f32;
-                    let y = y as f32;
-                    let z = z as f32;
+        let grid_size = size / step + 1;
+        let mut noise_map: VoxelGrid = VoxelGrid::new(grid_size, chunk_coord)
+            .with_step(step)
+            .with_apron(apron);
+
+        // World-space coordinate of the grid's first sample. Offsets are computed on integers so
+        // that neighbouring chunks sample their shared border at exactly the same positions.
+        let offset = chunk_coord * size as i32;
+        let step = step as i32;
+        let samples = -(apron as i32)..(grid_size + apron) as i32;
+
+        for z in samples.clone() {
+            for y in samples.clone() {
+                for x in samples.clone() {
+                    let x = (offset.x + x * step) as f32;
+                    let y = (offset.y + y * step) as f32;
+                    let z = (offset.z + z * step) as f32;
 
                     noise_map.push(self.generation_type.get_scalar(x, y, z));
                 }
             }
         }
 
+        self.materials.assign(&mut noise_map);
+
         noise_map
     }
 
@@ -149,10 +264,20 @@ pub trait NoiseGenerator: Send + Sync {
     fn get_scalar(&self, x: f32, y: f32, z: f32) -> f32;
 
     /// Get Scalar value using Vec3
-    #[allow(dead_code)]
     fn get_scalar_v(&self, pos: Vec3) -> f32 {
         self.get_scalar(pos.x, pos.y, pos.z)
     }
+
+    /// Central-difference gradient of the scalar field at `pos`
+    fn gradient(&self, pos: Vec3) -> Vec3 {
+        const H: f32 = 0.05;
+
+        Vec3::new(
+            self.get_scalar_v(pos + Vec3::X * H) - self.get_scalar_v(pos - Vec3::X * H),
+            self.get_scalar_v(pos + Vec3::Y * H) - self.get_scalar_v(pos - Vec3::Y * H),
+            self.get_scalar_v(pos + Vec3::Z * H) - self.get_scalar_v(pos - Vec3::Z * H),
+        ) / (2.0 * H)
+    }
 }
 
 pub struct NoiseDensity {
@@ -195,7 +320,7 @@ impl NoiseGenerator for NoiseDensity {
             let mut noise_val = self.noise.get_noise_2d(sample_x, sample_z);
             noise_val = (noise_val + 1.) / 2.;
 
-            noise_height = noise_val * amplitude;
+            noise_height += noise_val * amplitude;
 
             amplitude *= self.persistance;
             frequency *= self.lacunarity;
@@ -223,3 +348,27 @@ impl NoiseDensity {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_generator::terrain_noise::TerrainNoiseDensity;
+
+    #[test]
+    fn neighbouring_chunks_share_their_border_samples() {
+        let map_gen = MapGenerator::new(TerrainNoiseDensity::default());
+        let size = CHUNK_SIZE as usize;
+        let a = map_gen.generate_noise_lod(IVec3::new(0, 0, 0), size, 0, 0);
+        let b = map_gen.generate_noise_lod(IVec3::new(1, 0, 0), size, 0, 0);
+
+        for z in 0..a.size {
+            for y in 0..a.size {
+                assert_eq!(
+                    a.read(a.size - 1, y, z).to_bits(),
+                    b.read(0, y, z).to_bits(),
+                    "Samples differ at y {y} z {z}"
+                );
+            }
+        }
+    }
+}
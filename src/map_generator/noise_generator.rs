@@ -1,7 +1,11 @@
-use bevy::math::IVec3;
+use bevy::math::{IVec3, Vec3};
 use fastnoise_lite::FastNoiseLite;
 
-use crate::map_generator::endless_terrain::CHUNK_SIZE;
+use crate::map_generator::{
+    endless_terrain::CHUNK_SIZE,
+    voxel_material::VoxelMaterial,
+    voxel_storage::{Density, DensityFormat, Materials, Uniform, VoxelStorage},
+};
 
 pub struct Noise;
 
@@ -95,11 +99,24 @@ impl Noise {
 /// data: A 1D vector hold a list of value in a 3D space
 /// Accessing this require both asix x, y and z in this formular
 /// `x + size * (y + size * z)`
+///
+/// The grid can be padded by an apron of `apron` samples on each side, taken from the neighbouring
+/// chunks. Those are pushed along with the others but are only reachable through `read_apron`, so
+/// `x`, `y` and `z` still go from 0 to `size - 1` everywhere else.
+///
+/// Samples are written as plain vectors, `compress` shrinks them once the grid is only read.
 #[derive(Default, Debug, Clone)]
 pub struct VoxelGrid {
-    data: Vec<f32>,
+    data: Density,
+    /// Material of each sample, laid out like `data`
+    materials: Materials,
     pub size: usize,
     chunk_coord: IVec3,
+    /// Distance between 2 samples, `2^lod`
+    step: usize,
+    /// Number of samples beyond the chunk bounds on each side
+    apron: usize,
+    /// Bounds of the samples of the chunk itself, the apron being left out
     min: f32,
     max: f32,
 }
@@ -107,23 +124,54 @@ pub struct VoxelGrid {
 impl VoxelGrid {
     pub fn new(size: usize, chunk_coord: IVec3) -> Self {
         Self {
-            data: Vec::with_capacity(size.pow(3)),
+            data: Density::Raw(Vec::with_capacity(size.pow(3))),
+            materials: Materials::Raw(Vec::with_capacity(size.pow(3))),
             size,
             chunk_coord,
+            step: 1,
+            apron: 0,
             min: f32::MAX,
             max: f32::MIN,
         }
     }
 
+    /// Grid made of already known samples, apron included
+    pub fn from_samples(size: usize, chunk_coord: IVec3, apron: usize, data: Vec<f32>) -> Self {
+        let mut voxel_grid = Self::new(size, chunk_coord).with_apron(apron);
+        for value in data {
+            voxel_grid.push(value);
+        }
+        voxel_grid
+    }
+
     pub fn push(&mut self, value: f32) {
-        // Set noise bound
-        if value > self.max {
-            self.max = value;
-        } else if value < self.min {
-            self.min = value;
+        let padded_size = self.padded_size();
+        let idx = self.data.len();
+        let padded = [
+            idx % padded_size,
+            (idx / padded_size) % padded_size,
+            idx / padded_size.pow(2),
+        ];
+        let core = self.apron..self.apron + self.size;
+        if padded.iter().all(|axis| core.contains(axis)) {
+            self.extend_bounds(value);
         }
 
-        self.data.push(value);
+        self.data.raw_mut().push(value);
+        self.materials.raw_mut().push(VoxelMaterial::default());
+    }
+
+    pub fn with_step(mut self, step: usize) -> Self {
+        self.step = step;
+        self
+    }
+
+    /// Pad the grid with `apron` samples on each side. Must be set before pushing any value.
+    pub fn with_apron(mut self, apron: usize) -> Self {
+        self.apron = apron;
+        self.data = Density::Raw(Vec::with_capacity(self.padded_size().pow(3)));
+        self.materials = Materials::Raw(Vec::with_capacity(self.padded_size().pow(3)));
+        self
     }
 
     pub fn normalize(&mut self) {
@@ -133,12 +181,230 @@ impl VoxelGrid {
         // }
     }
 
+    pub fn chunk_coord(&self) -> IVec3 {
+        self.chunk_coord
+    }
+
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    pub fn apron(&self) -> usize {
+        self.apron
+    }
+
+    /// Number of samples per side, apron included
+    pub fn padded_size(&self) -> usize {
+        self.size + 2 * self.apron
+    }
+
+    /// World-space position of the grid's first sample
+    pub fn origin(&self) -> Vec3 {
+        (self.chunk_coord * ((self.size - 1) * self.step) as i32).as_vec3()
+    }
+
     pub fn read(&self, x: usize, y: usize, z: usize) -> f32 {
-        self.data[Self::to_1d(x, y, z, self.size)]
+        self.data.get(self.padded_index(x, y, z))
+    }
+
+    pub fn write(&mut self, x: usize, y: usize, z: usize, value: f32) {
+        let idx = self.padded_index(x, y, z);
+        self.data.raw_mut()[idx] = value;
+        self.extend_bounds(value);
+    }
+
+    pub fn material(&self, x: usize, y: usize, z: usize) -> VoxelMaterial {
+        self.materials.get(self.padded_index(x, y, z))
+    }
+
+    /// Set the material of a sample which may lie in the apron, each axis being in
+    /// `-apron..size + apron`
+    pub fn set_material_apron(&mut self, x: i32, y: i32, z: i32, material: VoxelMaterial) {
+        let apron = self.apron as i32;
+        let (x, y, z) = (
+            (x + apron) as usize,
+            (y + apron) as usize,
+            (z + apron) as usize,
+        );
+        let idx = Self::to_1d(x, y, z, self.padded_size());
+        self.materials.raw_mut()[idx] = material;
+    }
+
+    pub fn fill_material(&mut self, material: VoxelMaterial) {
+        self.materials = Materials::Uniform(Uniform::new(material, self.data.len()));
+    }
+
+    pub fn try_read(&self, x: usize, y: usize, z: usize) -> Option<f32> {
+        if x >= self.size || y >= self.size || z >= self.size {
+            return None;
+        }
+        Some(self.read(x, y, z))
+    }
+
+    /// Read a sample which may lie in the apron, each axis being in `-apron..size + apron`
+    pub fn read_apron(&self, x: i32, y: i32, z: i32) -> f32 {
+        let apron = self.apron as i32;
+        let (x, y, z) = (
+            (x + apron) as usize,
+            (y + apron) as usize,
+            (z + apron) as usize,
+        );
+        self.data.get(Self::to_1d(x, y, z, self.padded_size()))
+    }
+
+    /// Write a sample which may lie in the apron, each axis being in `-apron..size + apron`
+    pub fn write_apron(&mut self, x: i32, y: i32, z: i32, value: f32) {
+        let core = 0..self.size as i32;
+        if [x, y, z].iter().all(|axis| core.contains(axis)) {
+            self.extend_bounds(value);
+        }
+
+        let apron = self.apron as i32;
+        let (x, y, z) = (
+            (x + apron) as usize,
+            (y + apron) as usize,
+            (z + apron) as usize,
+        );
+        let idx = Self::to_1d(x, y, z, self.padded_size());
+        self.data.raw_mut()[idx] = value;
+    }
+
+    /// Every sample of the grid, apron included
+    pub fn samples(&self) -> impl Iterator<Item = f32> + '_ {
+        self.data.values()
     }
 
-    pub fn try_read(&self, x: usize, y: usize, z: usize) -> Option<&f32> {
-        self.data.get(Self::to_1d(x, y, z, self.size))
+    /// Lowest and highest samples ever stored in the chunk, apron excluded. Overwritten samples
+    /// are still accounted for until `update_bounds` is called, so the actual range may be
+    /// narrower.
+    pub fn bounds(&self) -> (f32, f32) {
+        (self.min, self.max)
+    }
+
+    /// Recompute the bounds from the current samples of the chunk, after some were overwritten
+    pub fn update_bounds(&mut self) {
+        (self.min, self.max) = (f32::MAX, f32::MIN);
+        for z in 0..self.size {
+            for y in 0..self.size {
+                for x in 0..self.size {
+                    let value = self.read(x, y, z);
+                    self.extend_bounds(value);
+                }
+            }
+        }
+    }
+
+    /// Widen the bounds to `value`, a sample of the chunk itself
+    fn extend_bounds(&mut self, value: f32) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    /// Whether the surface may go through the chunk, some of its samples being below the isovalue
+    /// and others above it. Only the apron may cross the surface when it is false, leaving nothing
+    /// to mesh.
+    pub fn straddles(&self, isovalue: f32) -> bool {
+        self.min < isovalue && self.max >= isovalue
+    }
+
+    /// Shrink the samples of a grid which is not going to be written to anymore.
+    ///
+    /// Grids entirely above or below the surface keep a single density, the one closest to the
+    /// surface, which is enough to tell solid from air but is not the actual field anymore.
+    pub fn compress(&mut self, format: DensityFormat, isovalue: f32) {
+        self.materials = self.materials.compress();
+
+        if self.data.is_empty() {
+            return;
+        }
+        if !self.straddles(isovalue) {
+            let closest = match self.max < isovalue {
+                true => self.max,
+                false => self.min,
+            };
+            self.data = Density::Uniform(Uniform::new(closest, self.data.len()));
+        } else {
+            self.data = self.data.convert(format);
+        }
+    }
+
+    /// Whether the density is still the one generated, `compress` having left it raw. Other grids
+    /// must be generated again before being edited, or the edited chunk would not match its
+    /// neighbours anymore.
+    pub fn is_raw(&self) -> bool {
+        matches!(self.data, Density::Raw(_))
+    }
+
+    /// Approximate number of bytes used by the samples
+    pub fn memory_usage(&self) -> usize {
+        self.data.memory_usage() + self.materials.memory_usage()
+    }
+
+    /// Replace the samples of the grid by the ones of `other` lying at the same world-space
+    /// positions
+    pub fn overlay(&mut self, other: &VoxelGrid) {
+        let apron = self.apron as i32;
+        let (other_apron, other_step) = (other.apron as i32, other.step as f32);
+        let other_samples = -other_apron..(other.size as i32 + other_apron);
+
+        let samples = -apron..(self.size as i32 + apron);
+        for z in samples.clone() {
+            for y in samples.clone() {
+                for x in samples.clone() {
+                    let pos = (self.sample_position(x, y, z) - other.origin()) / other_step;
+                    let idx = pos.round().as_ivec3();
+                    if idx.to_array().iter().all(|idx| other_samples.contains(idx)) {
+                        let value = other.read_apron(idx.x, idx.y, idx.z);
+                        self.write_apron(x, y, z, value);
+                    }
+                }
+            }
+        }
+    }
+
+    /// World-space position of a sample, apron included
+    pub fn sample_position(&self, x: i32, y: i32, z: i32) -> Vec3 {
+        self.origin() + (IVec3::new(x, y, z) * self.step as i32).as_vec3()
+    }
+
+    /// Trilinear interpolation of the samples at `pos`, relative to `origin`. Positions outside of
+    /// the grid are clamped onto its border.
+    pub fn interpolate(&self, pos: Vec3) -> f32 {
+        let last = (self.size - 1) as f32;
+        let pos = (pos / self.step as f32).clamp(Vec3::ZERO, Vec3::splat(last));
+        let lo = pos.floor().min(Vec3::splat(last - 1.0)).max(Vec3::ZERO);
+        let t = pos - lo;
+        let (x, y, z) = (lo.x as usize, lo.y as usize, lo.z as usize);
+
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let edge = |y: usize, z: usize| lerp(self.read(x, y, z), self.read(x + 1, y, z), t.x);
+        let face = |z: usize| lerp(edge(y, z), edge(y + 1, z), t.y);
+        lerp(face(z), face(z + 1), t.z)
+    }
+
+    fn padded_index(&self, x: usize, y: usize, z: usize) -> usize {
+        let apron = self.apron;
+        Self::to_1d(x + apron, y + apron, z + apron, self.padded_size())
+    }
+
+    /// Central-difference gradient at a grid point. Samples of the apron are used on the grid's
+    /// border, falling back to one-sided differences when there is none.
+    pub fn gradient(&self, x: usize, y: usize, z: usize) -> Vec3 {
+        let (lowest, highest) = (-(self.apron as i32), (self.size + self.apron) as i32 - 1);
+        let (x, y, z) = (x as i32, y as i32, z as i32);
+        let diff = |lo: (i32, i32, i32), hi: (i32, i32, i32), span: i32| {
+            (self.read_apron(hi.0, hi.1, hi.2) - self.read_apron(lo.0, lo.1, lo.2)) / span as f32
+        };
+
+        let (x0, x1) = ((x - 1).max(lowest), (x + 1).min(highest));
+        let (y0, y1) = ((y - 1).max(lowest), (y + 1).min(highest));
+        let (z0, z1) = ((z - 1).max(lowest), (z + 1).min(highest));
+
+        Vec3::new(
+            diff((x0, y, z), (x1, y, z), x1 - x0),
+            diff((x, y0, z), (x, y1, z), y1 - y0),
+            diff((x, y, z0), (x, y, z1), z1 - z0),
+        )
     }
 
     pub fn to_1d(x: usize, y: usize, z: usize, size: usize) -> usize {
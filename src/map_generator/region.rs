@@ -0,0 +1,388 @@
+//! Binary format of the region files worlds are saved in.
+//!
+//! A region holds up to `REGION_SIZE`^3 chunks. Its file starts with a header followed by the
+//! chunks, all numbers being little-endian:
+//!
+//! | Field              | Type                               |
+//! |--------------------|------------------------------------|
+//! | magic              | `b"MCRG"`                          |
+//! | version            | u16                                |
+//! | region coordinate  | 3 × i32                            |
+//! | generator          | u32 length + RON of the generator  |
+//! | chunk count        | u32                                |
+//! | header checksum    | u32, CRC32 of the fields above     |
+//!
+//! Each chunk then has a local index (u16), its grid size and apron (u32 each), the length of its
+//! zlib-compressed samples (u32), a checksum (u32) and the compressed bytes themselves. The
+//! checksum is the CRC32 of the chunk's fields and compressed bytes, so a corrupted index or size
+//! is caught as well.
+
+use std::{
+    fs,
+    io::{Read, Write},
+    mem::size_of,
+    path::{Path, PathBuf},
+};
+
+use bevy::math::IVec3;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use thiserror::Error;
+
+use super::noise_generator::VoxelGrid;
+
+/// Number of chunks per side of a region
+pub const REGION_SIZE: i32 = 8;
+
+const MAGIC: &[u8; 4] = b"MCRG";
+const VERSION: u16 = 2;
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum RegionError {
+    #[error("Could not access region file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Not a region file")]
+    InvalidMagic,
+    #[error("Unsupported region format version {0}, expected {VERSION}")]
+    UnsupportedVersion(u16),
+    #[error("Region file ends unexpectedly")]
+    Truncated,
+    #[error("Region header is corrupted")]
+    CorruptedHeader,
+    /// Position of the chunk in the file, its coordinate being untrusted
+    #[error("Chunk #{0} of the region is corrupted")]
+    CorruptedChunk(u32),
+    #[error("Chunk index {0} is outside of the region")]
+    InvalidChunkIndex(u16),
+    #[error("Chunk {0} is outside of region {1}")]
+    ChunkOutsideRegion(IVec3, IVec3),
+}
+
+/// Chunks of a region along with the generator they were generated with
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub coord: IVec3,
+    /// RON description of the terrain generator (seed and settings)
+    pub generator: String,
+    /// Full resolution density of the stored chunks
+    pub chunks: Vec<VoxelGrid>,
+}
+
+impl Region {
+    pub fn new(coord: IVec3, generator: String) -> Self {
+        Self {
+            coord,
+            generator,
+            chunks: Vec::new(),
+        }
+    }
+
+    /// Coordinate of the region containing the chunk at `chunk_coord`
+    pub fn containing(chunk_coord: IVec3) -> IVec3 {
+        chunk_coord.div_euclid(IVec3::splat(REGION_SIZE))
+    }
+
+    pub fn path(directory: &Path, coord: IVec3) -> PathBuf {
+        directory.join(format!("r.{}.{}.{}.region", coord.x, coord.y, coord.z))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RegionError> {
+        Self::decode(&fs::read(path)?)
+    }
+
+    /// Write the region next to its destination first, so a failed save never leaves a
+    /// half-written file behind
+    pub fn save(&self, directory: &Path) -> Result<(), RegionError> {
+        fs::create_dir_all(directory)?;
+        let path = Self::path(directory, self.coord);
+        let tmp_path = path.with_extension("region.tmp");
+        fs::write(&tmp_path, self.encode()?)?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, RegionError> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        for axis in self.coord.to_array() {
+            bytes.extend_from_slice(&axis.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(self.generator.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(self.generator.as_bytes());
+        bytes.extend_from_slice(&(self.chunks.len() as u32).to_le_bytes());
+        let header_checksum = crc32fast::hash(&bytes);
+        bytes.extend_from_slice(&header_checksum.to_le_bytes());
+
+        // Sorted so that the same chunks always give the same bytes
+        let mut chunks = self
+            .chunks
+            .iter()
+            .map(|voxel_grid| Ok((self.chunk_index(voxel_grid.chunk_coord())?, voxel_grid)))
+            .collect::<Result<Vec<_>, RegionError>>()?;
+        chunks.sort_by_key(|(idx, _)| *idx);
+
+        for (idx, voxel_grid) in chunks {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            for value in voxel_grid.samples() {
+                encoder.write_all(&value.to_le_bytes())?;
+            }
+            let compressed = encoder.finish()?;
+
+            let fields_start = bytes.len();
+            bytes.extend_from_slice(&idx.to_le_bytes());
+            bytes.extend_from_slice(&(voxel_grid.size as u32).to_le_bytes());
+            bytes.extend_from_slice(&(voxel_grid.apron() as u32).to_le_bytes());
+            bytes.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            let checksum = chunk_checksum(&bytes[fields_start..], &compressed);
+            bytes.extend_from_slice(&checksum.to_le_bytes());
+            bytes.extend_from_slice(&compressed);
+        }
+
+        Ok(bytes)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, RegionError> {
+        let mut reader = ByteReader { bytes, pos: 0 };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(RegionError::InvalidMagic);
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(RegionError::UnsupportedVersion(version));
+        }
+
+        let coord = IVec3::new(reader.i32()?, reader.i32()?, reader.i32()?);
+        let generator_len = reader.u32()? as usize;
+        let generator = reader.take(generator_len)?.to_vec();
+        let chunk_count = reader.u32()?;
+
+        let header_end = reader.pos;
+        if reader.u32()? != crc32fast::hash(&bytes[..header_end]) {
+            return Err(RegionError::CorruptedHeader);
+        }
+        let generator = String::from_utf8(generator).map_err(|_| RegionError::CorruptedHeader)?;
+
+        let mut region = Region::new(coord, generator);
+        for chunk_number in 0..chunk_count {
+            let fields_start = reader.pos;
+            let idx = reader.u16()?;
+            let size = reader.u32()? as usize;
+            let apron = reader.u32()? as usize;
+            let compressed_len = reader.u32()? as usize;
+            let fields = &bytes[fields_start..reader.pos];
+            let checksum = reader.u32()?;
+            let compressed = reader.take(compressed_len)?;
+            if chunk_checksum(fields, compressed) != checksum {
+                return Err(RegionError::CorruptedChunk(chunk_number));
+            }
+            let chunk_coord = region.chunk_coord(idx)?;
+
+            // Sizes matching their checksum are still checked before allocating
+            let expected_len = size
+                .checked_add(apron.saturating_mul(2))
+                .and_then(|padded_size| padded_size.checked_pow(3))
+                .and_then(|samples| samples.checked_mul(size_of::<f32>()))
+                .ok_or(RegionError::CorruptedChunk(chunk_number))?;
+
+            let mut raw = Vec::new();
+            ZlibDecoder::new(compressed)
+                .take(expected_len as u64 + 1)
+                .read_to_end(&mut raw)
+                .map_err(|_| RegionError::CorruptedChunk(chunk_number))?;
+            if size == 0 || raw.len() != expected_len {
+                return Err(RegionError::CorruptedChunk(chunk_number));
+            }
+
+            let samples = raw
+                .chunks_exact(size_of::<f32>())
+                .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+                .collect();
+            region
+                .chunks
+                .push(VoxelGrid::from_samples(size, chunk_coord, apron, samples));
+        }
+
+        Ok(region)
+    }
+
+    /// Index of the chunk at `chunk_coord` within the region
+    fn chunk_index(&self, chunk_coord: IVec3) -> Result<u16, RegionError> {
+        if Self::containing(chunk_coord) != self.coord {
+            return Err(RegionError::ChunkOutsideRegion(chunk_coord, self.coord));
+        }
+
+        let local = chunk_coord - self.coord * REGION_SIZE;
+        Ok((local.x + local.y * REGION_SIZE + local.z * REGION_SIZE.pow(2)) as u16)
+    }
+
+    fn chunk_coord(&self, idx: u16) -> Result<IVec3, RegionError> {
+        let idx = idx as i32;
+        if idx >= REGION_SIZE.pow(3) {
+            return Err(RegionError::InvalidChunkIndex(idx as u16));
+        }
+
+        let local = IVec3::new(
+            idx % REGION_SIZE,
+            (idx / REGION_SIZE) % REGION_SIZE,
+            idx / REGION_SIZE.pow(2),
+        );
+        Ok(self.coord * REGION_SIZE + local)
+    }
+}
+
+/// CRC32 of the fields of a chunk followed by its compressed samples
+fn chunk_checksum(fields: &[u8], compressed: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(fields);
+    hasher.update(compressed);
+    hasher.finalize()
+}
+
+/// Reads little-endian numbers, failing instead of panicking past the end of the bytes
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], RegionError> {
+        let end = self.pos.checked_add(len).ok_or(RegionError::Truncated)?;
+        let bytes = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or(RegionError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], RegionError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u16(&mut self) -> Result<u16, RegionError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, RegionError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn i32(&mut self) -> Result<i32, RegionError> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::Vec3;
+
+    use super::*;
+    use crate::map_generator::{
+        endless_terrain::CHUNK_SIZE,
+        sphere_noise::SphereNoiseDensity,
+        terrain_edit::{apply_brush, Brush, EditMode, TerrainEditEvent},
+        MapGenerator,
+    };
+
+    /// Region holding 2 edited chunks
+    fn edited_region() -> Region {
+        let map_gen = MapGenerator::new(SphereNoiseDensity::new(6.0));
+        let edit = TerrainEditEvent {
+            center: Vec3::new(12.0, 8.0, 8.0),
+            brush: Brush::Sphere { radius: 3.0 },
+            mode: EditMode::Subtract,
+        };
+
+        let mut region = Region::new(IVec3::ZERO, "generator".to_owned());
+        for chunk_coord in [IVec3::new(0, 0, 0), IVec3::new(1, 0, 0)] {
+            let mut voxel_grid = map_gen.generate_noise_lod(chunk_coord, CHUNK_SIZE as usize, 0, 1);
+            apply_brush(&mut voxel_grid, &edit, map_gen.isovalue());
+            region.chunks.push(voxel_grid);
+        }
+        region
+    }
+
+    #[test]
+    fn round_trip_gives_the_same_bytes() {
+        let bytes = edited_region().encode().unwrap();
+        let decoded = Region::decode(&bytes).unwrap();
+        assert_eq!(decoded.encode().unwrap(), bytes);
+    }
+
+    #[test]
+    fn round_trip_keeps_every_sample() {
+        let region = edited_region();
+        let decoded = Region::decode(&region.encode().unwrap()).unwrap();
+
+        assert_eq!(decoded.coord, region.coord);
+        assert_eq!(decoded.generator, region.generator);
+        assert_eq!(decoded.chunks.len(), region.chunks.len());
+        for voxel_grid in &region.chunks {
+            let decoded_grid = decoded
+                .chunks
+                .iter()
+                .find(|decoded_grid| decoded_grid.chunk_coord() == voxel_grid.chunk_coord())
+                .unwrap();
+            assert_eq!(decoded_grid.size, voxel_grid.size);
+            assert_eq!(decoded_grid.apron(), voxel_grid.apron());
+            assert!(decoded_grid
+                .samples()
+                .map(f32::to_bits)
+                .eq(voxel_grid.samples().map(f32::to_bits)));
+        }
+    }
+
+    #[test]
+    fn flipped_payload_byte_is_detected() {
+        let mut bytes = edited_region().encode().unwrap();
+        // Last byte of the last chunk's compressed samples
+        *bytes.last_mut().unwrap() ^= 0x01;
+
+        assert!(matches!(
+            Region::decode(&bytes),
+            Err(RegionError::CorruptedChunk(_))
+        ));
+    }
+
+    #[test]
+    fn flipped_chunk_field_byte_is_detected() {
+        let region = edited_region();
+        let bytes = region.encode().unwrap();
+        // Magic, version, coordinate, generator, chunk count and header checksum
+        let fields_start = MAGIC.len() + 2 + 3 * 4 + 4 + region.generator.len() + 4 + 4;
+
+        // Index, grid size, apron and compressed length of the first chunk
+        for pos in fields_start..fields_start + 2 + 3 * 4 {
+            let mut corrupted = bytes.clone();
+            corrupted[pos] ^= 0x01;
+            assert!(
+                Region::decode(&corrupted).is_err(),
+                "Flipped byte {pos} was not detected"
+            );
+        }
+
+        // The index alone points at another chunk of the region
+        let mut corrupted = bytes;
+        corrupted[fields_start] ^= 0x01;
+        assert!(matches!(
+            Region::decode(&corrupted),
+            Err(RegionError::CorruptedChunk(0))
+        ));
+    }
+
+    #[test]
+    fn truncated_file_is_detected() {
+        let bytes = edited_region().encode().unwrap();
+
+        for len in 0..bytes.len() {
+            assert!(
+                matches!(Region::decode(&bytes[..len]), Err(RegionError::Truncated)),
+                "Region truncated to {len} bytes was not detected"
+            );
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! Runtime editing of the terrain with brushes.
+//!
+//! Brushes are applied to the density each chunk was meshed from, then only the chunks they
+//! touched are meshed again. Chunks whose density was compressed are generated again instead. A
+//! brush crossing a border edits the shared (and apron) samples of every chunk involved, so both
+//! sides stay seamless. Every edit is also recorded in the `ChunkStore` so it is applied again
+//! when the chunk is regenerated.
+
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+
+use crate::settings::render::RenderSettings;
+
+use super::{
+    chunk_store::ChunkStore,
+    endless_terrain::{Chunk, ChunkMap, CHUNK_SIZE},
+    map_display::RenderChunk,
+    noise_generator::VoxelGrid,
+    MapGenerator,
+};
+
+pub struct TerrainEditPlugin;
+
+impl Plugin for TerrainEditPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ChunkStore>()
+            .add_event::<TerrainEditEvent>()
+            .add_systems(
+                Update,
+                apply_terrain_edits.run_if(resource_exists::<MapGenerator>),
+            );
+    }
+}
+
+/// Shape of the volume added to or removed from the terrain, centered on the edit position
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Brush {
+    Sphere {
+        radius: f32,
+    },
+    Cube {
+        half_extents: Vec3,
+    },
+    /// Upright cylinder
+    Cylinder {
+        radius: f32,
+        half_height: f32,
+    },
+}
+
+impl Brush {
+    /// Signed distance from `pos` (relative to the brush's center) to the brush's surface,
+    /// negative inside of it
+    pub fn distance(&self, pos: Vec3) -> f32 {
+        match *self {
+            Brush::Sphere { radius } => pos.length() - radius,
+            Brush::Cube { half_extents } => {
+                let q = pos.abs() - half_extents;
+                q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+            }
+            Brush::Cylinder {
+                radius,
+                half_height,
+            } => {
+                let q = Vec2::new(pos.xz().length() - radius, pos.y.abs() - half_height);
+                q.max(Vec2::ZERO).length() + q.max_element().min(0.0)
+            }
+        }
+    }
+
+    /// Half size of the box bounding the brush
+    pub fn half_extents(&self) -> Vec3 {
+        match *self {
+            Brush::Sphere { radius } => Vec3::splat(radius),
+            Brush::Cube { half_extents } => half_extents,
+            Brush::Cylinder {
+                radius,
+                half_height,
+            } => Vec3::new(radius, half_height, radius),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditMode {
+    /// Fill the brush with terrain
+    Add,
+    /// Dig the brush out of the terrain
+    Subtract,
+}
+
+/// Request to apply `brush` at `center`, in world space
+#[derive(Event, Debug, Clone, Copy)]
+pub struct TerrainEditEvent {
+    pub center: Vec3,
+    pub brush: Brush,
+    pub mode: EditMode,
+}
+
+fn apply_terrain_edits(
+    mut commands: Commands,
+    mut edit_events: EventReader<TerrainEditEvent>,
+    mut chunk_map: ResMut<ChunkMap>,
+    mut chunk_store: ResMut<ChunkStore>,
+    map_gen: Res<MapGenerator>,
+    render_cfg: Res<RenderSettings>,
+) {
+    let mut touched = HashSet::new();
+    let mut regenerate = HashSet::new();
+
+    for event in edit_events.read() {
+        // Samples of the apron lie (at most a few voxels) outside of their chunk, so neighbours of
+        // the chunks overlapping the brush are looked at too
+        let half_extents = event.brush.half_extents();
+        let lower = ((event.center - half_extents) / CHUNK_SIZE as f32)
+            .floor()
+            .as_ivec3()
+            - IVec3::ONE;
+        let upper = ((event.center + half_extents) / CHUNK_SIZE as f32)
+            .floor()
+            .as_ivec3()
+            + IVec3::ONE;
+
+        for z in lower.z..=upper.z {
+            for y in lower.y..=upper.y {
+                for x in lower.x..=upper.x {
+                    let chunk_coord = IVec3::new(x, y, z);
+                    match chunk_map.0.get_mut(&chunk_coord) {
+                        // Grids about to be replaced by a pending generation, or generated at
+                        // another level of detail, are stale
+                        Some(Chunk {
+                            voxel_grid: Some(voxel_grid),
+                            task: None,
+                            lods,
+                            ..
+                        }) if voxel_grid.is_raw() && voxel_grid.step() == 1 << lods.level() => {
+                            if apply_brush(voxel_grid, event, map_gen.isovalue()) {
+                                chunk_store.record(chunk_coord, *event);
+                                touched.insert(chunk_coord);
+                            }
+                        }
+                        // Chunks that are unloaded, (re)generating or compressed only record the
+                        // edit, it is applied once they are generated again at full precision
+                        chunk => {
+                            let margin = render_cfg.chunk_apron as f32 + 1.0;
+                            if brush_overlaps_chunk(event, chunk_coord, margin) {
+                                chunk_store.record(chunk_coord, *event);
+                                if chunk.is_some() {
+                                    regenerate.insert(chunk_coord);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    for chunk_coord in touched {
+        commands.add(RenderChunk::remesh(chunk_coord));
+    }
+    for chunk_coord in regenerate {
+        commands.add(RenderChunk::new(chunk_coord));
+    }
+}
+
+/// Whether the brush of `event` reaches the chunk at `chunk_coord`, its bounds being grown by
+/// `margin` voxels on each side
+fn brush_overlaps_chunk(event: &TerrainEditEvent, chunk_coord: IVec3, margin: f32) -> bool {
+    let chunk_min = (chunk_coord * CHUNK_SIZE as i32).as_vec3() - margin;
+    let chunk_max = chunk_min + CHUNK_SIZE as f32 + 2.0 * margin;
+    let half_extents = event.brush.half_extents();
+
+    (event.center + half_extents).cmpge(chunk_min).all()
+        && (event.center - half_extents).cmple(chunk_max).all()
+}
+
+/// Apply the brush of `event` to every sample of the grid it covers, apron included, then update
+/// the grid's bounds. Returns whether any sample changed.
+pub fn apply_brush(voxel_grid: &mut VoxelGrid, event: &TerrainEditEvent, isovalue: f32) -> bool {
+    let half_extents = event.brush.half_extents();
+    let apron = voxel_grid.apron() as i32;
+    let samples = -apron..(voxel_grid.size as i32 + apron);
+
+    let mut changed = false;
+    for z in samples.clone() {
+        for y in samples.clone() {
+            for x in samples.clone() {
+                let pos = voxel_grid.sample_position(x, y, z) - event.center;
+                // Leave some room around the brush so the field stays continuous near its surface
+                if pos
+                    .abs()
+                    .cmpgt(half_extents + voxel_grid.step() as f32)
+                    .any()
+                {
+                    continue;
+                }
+
+                let distance = event.brush.distance(pos);
+                let value = voxel_grid.read_apron(x, y, z);
+                // Union with the brush when adding, subtraction when digging
+                let edited = match event.mode {
+                    EditMode::Add => value.min(isovalue + distance),
+                    EditMode::Subtract => value.max(isovalue - distance),
+                };
+
+                if edited != value {
+                    voxel_grid.write_apron(x, y, z, edited);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    // Digging may leave the chunk entirely above the surface
+    if changed {
+        voxel_grid.update_bounds();
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_generator::density_graph::Constant;
+
+    #[test]
+    fn brush_distance_is_signed() {
+        let sphere = Brush::Sphere { radius: 3.0 };
+        assert_eq!(sphere.distance(Vec3::ZERO), -3.0);
+        assert_eq!(sphere.distance(Vec3::new(0.0, 3.0, 0.0)), 0.0);
+        assert_eq!(sphere.distance(Vec3::new(0.0, 0.0, 5.0)), 2.0);
+
+        let cube = Brush::Cube {
+            half_extents: Vec3::new(2.0, 3.0, 4.0),
+        };
+        assert_eq!(cube.distance(Vec3::ZERO), -2.0);
+        assert_eq!(cube.distance(Vec3::new(0.0, 3.0, 0.0)), 0.0);
+        assert_eq!(cube.distance(Vec3::new(0.0, 0.0, -6.0)), 2.0);
+        // Closest to an edge of the cube
+        assert_eq!(cube.distance(Vec3::new(5.0, 7.0, 0.0)), 5.0);
+
+        let cylinder = Brush::Cylinder {
+            radius: 2.0,
+            half_height: 3.0,
+        };
+        assert_eq!(cylinder.distance(Vec3::ZERO), -2.0);
+        assert_eq!(cylinder.distance(Vec3::new(2.0, 0.0, 0.0)), 0.0);
+        assert_eq!(cylinder.distance(Vec3::new(0.0, -3.0, 0.0)), 0.0);
+        assert_eq!(cylinder.distance(Vec3::new(0.0, 0.0, 4.0)), 2.0);
+        assert_eq!(cylinder.distance(Vec3::new(0.0, 5.0, 0.0)), 2.0);
+    }
+
+    #[test]
+    fn dig_only_removes_samples_inside_the_brush() {
+        let size = CHUNK_SIZE as usize;
+        let mut voxel_grid =
+            MapGenerator::new(Constant(-10.0)).generate_noise_lod(IVec3::ZERO, size, 0, 0);
+        // Off the grid, so no sample lies exactly on the brush's surface
+        let edit = TerrainEditEvent {
+            center: Vec3::splat(8.5),
+            brush: Brush::Sphere { radius: 3.0 },
+            mode: EditMode::Subtract,
+        };
+        assert!(apply_brush(&mut voxel_grid, &edit, 0.0));
+
+        for z in 0..size {
+            for y in 0..size {
+                for x in 0..size {
+                    let pos = Vec3::new(x as f32, y as f32, z as f32);
+                    let inside = pos.distance(edit.center) < 3.0;
+                    let is_air = voxel_grid.read(x, y, z) >= 0.0;
+                    assert_eq!(is_air, inside, "Sample {pos} was dug wrongly");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn digging_out_a_chunk_narrows_its_bounds() {
+        let mut voxel_grid = MapGenerator::new(Constant(-10.0)).generate_noise_lod(
+            IVec3::ZERO,
+            CHUNK_SIZE as usize,
+            0,
+            1,
+        );
+        let edit = TerrainEditEvent {
+            center: Vec3::splat(8.0),
+            brush: Brush::Cube {
+                half_extents: Vec3::splat(12.0),
+            },
+            mode: EditMode::Subtract,
+        };
+        apply_brush(&mut voxel_grid, &edit, 0.0);
+
+        let (min, _) = voxel_grid.bounds();
+        assert!(min >= 0.0, "Dug out chunk still has a solid bound {min}");
+        assert!(!voxel_grid.straddles(0.0));
+    }
+}
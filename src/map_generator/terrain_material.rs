@@ -0,0 +1,201 @@
+//! Triplanar texturing of the terrain.
+//!
+//! Marching cubes meshes have no UVs, so textures are projected along the 3 world axes and blended
+//! by the surface normal instead. Each `VoxelMaterial` is a layer of a texture array, blended
+//! between vertices with the weights of `ATTRIBUTE_MATERIAL_WEIGHTS_A`/`_B`.
+
+use bevy::{
+    asset::LoadState,
+    ecs::system::EntityCommands,
+    pbr::{ExtendedMaterial, MaterialExtension, MaterialExtensionKey, MaterialExtensionPipeline},
+    prelude::*,
+    render::{
+        mesh::{MeshVertexAttribute, MeshVertexBufferLayoutRef},
+        render_resource::{
+            AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError,
+            TextureDimension, VertexFormat,
+        },
+        texture::{ImageAddressMode, ImageSampler, ImageSamplerDescriptor},
+    },
+};
+
+use crate::settings::render::TerrainMaterialSettings;
+
+use super::{
+    endless_terrain::ChunkCoord, map_display::poll_chunk_meshes, voxel_material::VoxelMaterial,
+};
+
+const SHADER_PATH: &str = "shaders/mesh_renderer.wgsl";
+
+/// Weights of the first 4 `VoxelMaterial`s
+pub const ATTRIBUTE_MATERIAL_WEIGHTS_A: MeshVertexAttribute =
+    MeshVertexAttribute::new("MaterialWeightsA", 318_706_051, VertexFormat::Float32x4);
+/// Weights of the next 4 `VoxelMaterial`s
+pub const ATTRIBUTE_MATERIAL_WEIGHTS_B: MeshVertexAttribute =
+    MeshVertexAttribute::new("MaterialWeightsB", 318_706_052, VertexFormat::Float32x4);
+
+pub type TerrainMaterial = ExtendedMaterial<StandardMaterial, TriplanarExtension>;
+
+pub struct TerrainMaterialPlugin;
+
+impl Plugin for TerrainMaterialPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MaterialPlugin::<TerrainMaterial>::default())
+            .add_systems(Startup, setup_terrain_material)
+            .add_systems(
+                Update,
+                // Chunks finished in the same frame must get their material before the swap
+                apply_terrain_textures
+                    .after(poll_chunk_meshes)
+                    .run_if(resource_exists::<TerrainTextures>),
+            );
+    }
+}
+
+#[derive(Asset, AsBindGroup, Reflect, Debug, Clone)]
+pub struct TriplanarExtension {
+    /// Number of texture repetitions per world unit
+    #[uniform(100)]
+    pub texture_scale: f32,
+    /// How sharply the 3 projections are blended
+    #[uniform(100)]
+    pub blend_sharpness: f32,
+    /// One layer per `VoxelMaterial`
+    #[texture(101, dimension = "2d_array")]
+    #[sampler(102)]
+    pub textures: Handle<Image>,
+}
+
+impl MaterialExtension for TriplanarExtension {
+    fn vertex_shader() -> ShaderRef {
+        SHADER_PATH.into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        SHADER_PATH.into()
+    }
+
+    fn specialize(
+        _pipeline: &MaterialExtensionPipeline,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayoutRef,
+        _key: MaterialExtensionKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        // Prepasses (shadows included) keep Bevy's shaders, which only need positions and normals
+        if descriptor
+            .vertex
+            .shader_defs
+            .contains(&"PREPASS_PIPELINE".into())
+        {
+            return Ok(());
+        }
+
+        descriptor.vertex.buffers = vec![layout.0.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+            ATTRIBUTE_MATERIAL_WEIGHTS_A.at_shader_location(8),
+            ATTRIBUTE_MATERIAL_WEIGHTS_B.at_shader_location(9),
+        ])?];
+
+        Ok(())
+    }
+}
+
+/// Material shared by every chunk
+#[derive(Resource, Clone)]
+pub enum ChunkMaterial {
+    /// Colours taken from the vertices, used while textures are loading or when they are missing
+    Fallback(Handle<StandardMaterial>),
+    Triplanar(Handle<TerrainMaterial>),
+}
+
+impl ChunkMaterial {
+    pub fn insert(&self, entity: &mut EntityCommands) {
+        match self {
+            ChunkMaterial::Fallback(material) => entity.try_insert(material.clone()),
+            ChunkMaterial::Triplanar(material) => entity.try_insert(material.clone()),
+        };
+    }
+}
+
+/// Textures being loaded, replaced by the triplanar material once they are
+#[derive(Resource)]
+struct TerrainTextures(Handle<Image>);
+
+fn setup_terrain_material(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    asset_server: Res<AssetServer>,
+    material_cfg: Res<TerrainMaterialSettings>,
+) {
+    commands.insert_resource(ChunkMaterial::Fallback(materials.add(Color::WHITE)));
+
+    match &material_cfg.textures {
+        Some(path) => commands.insert_resource(TerrainTextures(asset_server.load(path))),
+        None => info!("No terrain textures configured, using vertex colours"),
+    }
+}
+
+/// Switch every chunk to the triplanar material once its textures are loaded
+fn apply_terrain_textures(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut terrain_materials: ResMut<Assets<TerrainMaterial>>,
+    asset_server: Res<AssetServer>,
+    textures: Res<TerrainTextures>,
+    material_cfg: Res<TerrainMaterialSettings>,
+    chunks_q: Query<Entity, (With<ChunkCoord>, With<Handle<StandardMaterial>>)>,
+) {
+    match asset_server.get_load_state(&textures.0) {
+        Some(LoadState::Loaded) => (),
+        Some(LoadState::Failed(err)) => {
+            warn!("Could not load terrain textures, using vertex colours: {err}");
+            commands.remove_resource::<TerrainTextures>();
+            return;
+        }
+        _ => return,
+    }
+    commands.remove_resource::<TerrainTextures>();
+
+    let Some(image) = images.get_mut(&textures.0) else {
+        return;
+    };
+
+    let layers = VoxelMaterial::ALL.len() as u32;
+    let is_stacked = image.texture_descriptor.dimension == TextureDimension::D2
+        && image.texture_descriptor.size.depth_or_array_layers == 1
+        && image.height() % layers == 0;
+    if !is_stacked {
+        warn!(
+            "Terrain textures must stack {layers} square textures vertically, using vertex colours"
+        );
+        return;
+    }
+
+    image.reinterpret_stacked_2d_as_array(layers);
+    image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+        address_mode_u: ImageAddressMode::Repeat,
+        address_mode_v: ImageAddressMode::Repeat,
+        ..ImageSamplerDescriptor::linear()
+    });
+
+    let material = terrain_materials.add(ExtendedMaterial {
+        base: StandardMaterial {
+            perceptual_roughness: 0.9,
+            ..default()
+        },
+        extension: TriplanarExtension {
+            texture_scale: material_cfg.texture_scale,
+            blend_sharpness: material_cfg.blend_sharpness,
+            textures: textures.0.clone(),
+        },
+    });
+
+    for entity in chunks_q.iter() {
+        commands
+            .entity(entity)
+            .remove::<Handle<StandardMaterial>>()
+            .insert(material.clone());
+    }
+    commands.insert_resource(ChunkMaterial::Triplanar(material));
+}
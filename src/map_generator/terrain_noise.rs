@@ -0,0 +1,138 @@
+use fastnoise_lite::FastNoiseLite;
+
+use super::NoiseGenerator;
+
+/// Terrain made of a heightmap carved by 3D noise, which gives overhangs, arches and caves
+pub struct TerrainNoiseDensity {
+    /// 2D noise shaping the surface height
+    height_noise: FastNoiseLite,
+    /// 3D noise added on top of the heightmap
+    density_noise: FastNoiseLite,
+    octaves: u32,
+    persistance: f32,
+    lacunarity: f32,
+    /// Average height of the surface
+    base_height: f32,
+    /// Maximum distance between the heightmap and `base_height`
+    height_amplitude: f32,
+    /// Strength of the 3D noise. The higher it is, the more the terrain is carved.
+    density_amplitude: f32,
+}
+
+impl Default for TerrainNoiseDensity {
+    fn default() -> Self {
+        Self::new(6969, 0.01, 4, 0.5, 2.0)
+    }
+}
+
+impl TerrainNoiseDensity {
+    pub fn new(seed: i32, frequency: f32, octaves: u32, persistance: f32, lacunarity: f32) -> Self {
+        let mut height_noise = FastNoiseLite::new();
+        height_noise.set_noise_type(Some(fastnoise_lite::NoiseType::Perlin));
+        height_noise.set_seed(Some(seed));
+        height_noise.set_frequency(Some(frequency));
+
+        // Use a different seed so both noises are not correlated
+        let mut density_noise = FastNoiseLite::new();
+        density_noise.set_noise_type(Some(fastnoise_lite::NoiseType::Perlin));
+        density_noise.set_seed(Some(seed.wrapping_add(1)));
+        density_noise.set_frequency(Some(frequency));
+
+        Self {
+            height_noise,
+            density_noise,
+            octaves,
+            persistance,
+            lacunarity,
+            base_height: 0.0,
+            height_amplitude: 24.0,
+            density_amplitude: 12.0,
+        }
+    }
+
+    pub fn with_heights(
+        mut self,
+        base_height: f32,
+        height_amplitude: f32,
+        density_amplitude: f32,
+    ) -> Self {
+        self.base_height = base_height;
+        self.height_amplitude = height_amplitude;
+        self.density_amplitude = density_amplitude;
+        self
+    }
+}
+
+/// Sum `octaves` layers of noise, each one with a higher frequency and a lower amplitude than the
+/// previous one. The result is normalized back to the -1..1 range.
+pub(super) fn fbm(
+    octaves: u32,
+    persistance: f32,
+    lacunarity: f32,
+    sample: impl Fn(f32) -> f32,
+) -> f32 {
+    let mut amplitude = 1f32;
+    let mut frequency = 1f32;
+    let mut noise_value = 0f32;
+    let mut max_value = 0f32;
+
+    for _ in 0..octaves {
+        noise_value += sample(frequency) * amplitude;
+        max_value += amplitude;
+
+        amplitude *= persistance;
+        frequency *= lacunarity;
+    }
+
+    if max_value > 0.0 {
+        noise_value / max_value
+    } else {
+        0.0
+    }
+}
+
+impl NoiseGenerator for TerrainNoiseDensity {
+    fn get_scalar(&self, x: f32, y: f32, z: f32) -> f32 {
+        let (octaves, persistance, lacunarity) = (self.octaves, self.persistance, self.lacunarity);
+
+        let height = fbm(octaves, persistance, lacunarity, |freq| {
+            self.height_noise.get_noise_2d(x * freq, z * freq)
+        });
+        let density = fbm(octaves, persistance, lacunarity, |freq| {
+            self.density_noise
+                .get_noise_3d(x * freq, y * freq, z * freq)
+        });
+
+        // Positive above the surface (air), negative under it (solid)
+        y - (self.base_height + height * self.height_amplitude) + density * self.density_amplitude
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// CRC32 of the densities sampled below. Only update it when the terrain is meant to change.
+    const CHECKSUM: u32 = 0xfa05_47a8;
+
+    #[test]
+    fn fixed_seed_gives_the_same_terrain() {
+        let density = TerrainNoiseDensity::new(1234, 0.02, 4, 0.5, 2.0);
+
+        let mut hasher = crc32fast::Hasher::new();
+        for z in -4..4 {
+            for y in -4..4 {
+                for x in -4..4 {
+                    let value = density.get_scalar(x as f32 * 3.5, y as f32 * 3.5, z as f32 * 3.5);
+                    hasher.update(&value.to_bits().to_le_bytes());
+                }
+            }
+        }
+
+        let checksum = hasher.finalize();
+        assert_eq!(
+            checksum, CHECKSUM,
+            "Terrain changed, checksum is {checksum:#010x}"
+        );
+    }
+}
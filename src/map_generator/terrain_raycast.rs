@@ -0,0 +1,91 @@
+//! Rays cast against the density of the loaded chunks.
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use super::{
+    endless_terrain::{ChunkMap, CHUNK_SIZE},
+    MapGenerator,
+};
+
+/// Distance between the samples taken along a ray, smaller than a voxel so thin features are not
+/// stepped over
+const MARCH_STEP: f32 = 0.5;
+/// Halvings of the step the surface was found in
+const BISECTION_STEPS: u32 = 8;
+/// Distance between the samples of the central differences the normal is estimated with
+const NORMAL_EPSILON: f32 = 0.05;
+
+/// Where a ray hit the terrain
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainHit {
+    pub position: Vec3,
+    /// Surface normal at `position`, pointing out of the terrain
+    pub normal: Vec3,
+    /// Distance from the origin of the ray to `position`
+    pub distance: f32,
+    pub chunk_coord: IVec3,
+}
+
+/// Raycasts against the terrain, for any system to use.
+///
+/// Rays march the density field in fixed steps, then refine the step the surface was crossed in
+/// by bisection. Chunks which are not generated yet are seen through.
+#[derive(SystemParam)]
+pub struct TerrainRaycast<'w> {
+    chunk_map: Res<'w, ChunkMap>,
+    map_gen: Option<Res<'w, MapGenerator>>,
+}
+
+impl TerrainRaycast<'_> {
+    /// First point of the terrain along `ray`, within `max_distance`
+    pub fn cast(&self, ray: Ray3d, max_distance: f32) -> Option<TerrainHit> {
+        let isovalue = self.map_gen.as_ref()?.isovalue();
+        let is_solid = |distance: f32| {
+            self.chunk_map
+                .density_at(ray.get_point(distance))
+                .is_some_and(|density| density < isovalue)
+        };
+
+        let steps = (max_distance / MARCH_STEP).ceil() as u32;
+        let hit_step =
+            (0..=steps).find(|&i| is_solid((i as f32 * MARCH_STEP).min(max_distance)))?;
+
+        // The ray starts inside the terrain
+        if hit_step == 0 {
+            return Some(self.hit(ray, 0.0));
+        }
+
+        let (mut outside, mut inside) = (
+            (hit_step - 1) as f32 * MARCH_STEP,
+            (hit_step as f32 * MARCH_STEP).min(max_distance),
+        );
+        for _ in 0..BISECTION_STEPS {
+            let middle = (outside + inside) / 2.0;
+            match is_solid(middle) {
+                true => inside = middle,
+                false => outside = middle,
+            }
+        }
+
+        Some(self.hit(ray, inside))
+    }
+
+    fn hit(&self, ray: Ray3d, distance: f32) -> TerrainHit {
+        let position = ray.get_point(distance);
+
+        // Density grows towards the air, so its gradient points out of the terrain
+        let density = |offset: Vec3| self.chunk_map.density_at(position + offset).unwrap_or(0.0);
+        let gradient = Vec3::new(
+            density(Vec3::X * NORMAL_EPSILON) - density(Vec3::NEG_X * NORMAL_EPSILON),
+            density(Vec3::Y * NORMAL_EPSILON) - density(Vec3::NEG_Y * NORMAL_EPSILON),
+            density(Vec3::Z * NORMAL_EPSILON) - density(Vec3::NEG_Z * NORMAL_EPSILON),
+        );
+
+        TerrainHit {
+            position,
+            normal: gradient.try_normalize().unwrap_or(-*ray.direction),
+            distance,
+            chunk_coord: (position / CHUNK_SIZE as f32).floor().as_ivec3(),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use bevy::{color::Color, math::Vec3};
+use fastnoise_lite::FastNoiseLite;
+use serde::{Deserialize, Serialize};
+
+use super::noise_generator::VoxelGrid;
+
+/// What a voxel is made of
+#[repr(u8)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum VoxelMaterial {
+    Rock,
+    Dirt,
+    #[default]
+    Grass,
+    Sand,
+    Snow,
+}
+
+impl VoxelMaterial {
+    /// Every material, in the order of their layer in the terrain textures
+    pub const ALL: [VoxelMaterial; 5] = [
+        VoxelMaterial::Rock,
+        VoxelMaterial::Dirt,
+        VoxelMaterial::Grass,
+        VoxelMaterial::Sand,
+        VoxelMaterial::Snow,
+    ];
+
+    /// Colour given to the vertices made of this material
+    pub fn color(&self) -> Color {
+        match self {
+            VoxelMaterial::Rock => Color::srgb_u8(110, 110, 110),
+            VoxelMaterial::Dirt => Color::srgb_u8(120, 85, 50),
+            VoxelMaterial::Grass => Color::srgb_u8(0, 250, 0),
+            VoxelMaterial::Sand => Color::srgb_u8(220, 200, 140),
+            VoxelMaterial::Snow => Color::srgb_u8(245, 245, 250),
+        }
+    }
+}
+
+/// Material given to the samples matching all of its conditions
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MaterialRule {
+    pub material: VoxelMaterial,
+    /// World-space height range
+    #[serde(default = "MaterialRule::any")]
+    pub height: (f32, f32),
+    /// Slope range in degrees, 0 being flat ground
+    #[serde(default = "MaterialRule::any")]
+    pub slope: (f32, f32),
+    #[serde(default)]
+    pub noise: Option<MaterialNoise>,
+}
+
+impl MaterialRule {
+    fn any() -> (f32, f32) {
+        (f32::MIN, f32::MAX)
+    }
+
+    /// Whether a sample at `pos` on a slope of `slope` degrees is made of this rule's material.
+    /// `noise` is the built `MaterialRule::noise`.
+    fn matches(&self, pos: Vec3, slope: f32, noise: Option<&FastNoiseLite>) -> bool {
+        let in_range = |value: f32, (min, max): (f32, f32)| (min..=max).contains(&value);
+        let in_noise = match (&self.noise, noise) {
+            (Some(material_noise), Some(noise)) => {
+                noise.get_noise_3d(pos.x, pos.y, pos.z) > material_noise.threshold
+            }
+            _ => true,
+        };
+
+        in_range(pos.y, self.height) && in_range(slope, self.slope) && in_noise
+    }
+}
+
+/// Patches of material where a noise goes over `threshold`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MaterialNoise {
+    pub seed: i32,
+    pub frequency: f32,
+    /// Noise value (in the -1..1 range) above which the rule applies
+    pub threshold: f32,
+}
+
+impl MaterialNoise {
+    fn build(&self) -> FastNoiseLite {
+        let mut noise = FastNoiseLite::new();
+        noise.set_noise_type(Some(fastnoise_lite::NoiseType::Perlin));
+        noise.set_seed(Some(self.seed));
+        noise.set_frequency(Some(self.frequency));
+        noise
+    }
+}
+
+/// Assigns a material to every sample of a grid. The first matching rule wins, samples matching
+/// none are made of the `default` material.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct MaterialRules {
+    #[serde(default)]
+    pub default: VoxelMaterial,
+    #[serde(default)]
+    pub rules: Vec<MaterialRule>,
+}
+
+impl MaterialRules {
+    pub fn assign(&self, voxel_grid: &mut VoxelGrid) {
+        if self.rules.is_empty() {
+            voxel_grid.fill_material(self.default);
+            return;
+        }
+
+        let noises: Vec<_> = self
+            .rules
+            .iter()
+            .map(|rule| rule.noise.as_ref().map(MaterialNoise::build))
+            .collect();
+
+        let apron = voxel_grid.apron() as i32;
+        let samples = -apron..(voxel_grid.size as i32 + apron);
+        let last = voxel_grid.size as i32 - 1;
+
+        for z in samples.clone() {
+            for y in samples.clone() {
+                for x in samples.clone() {
+                    let pos = voxel_grid.sample_position(x, y, z);
+
+                    // Gradients are only known within the grid, the apron takes the slope of the
+                    // closest sample
+                    let closest = |idx: i32| idx.clamp(0, last) as usize;
+                    let gradient = voxel_grid.gradient(closest(x), closest(y), closest(z));
+                    let slope = gradient
+                        .try_normalize()
+                        .map_or(0.0, |normal| normal.angle_between(Vec3::Y).to_degrees());
+
+                    let material = self
+                        .rules
+                        .iter()
+                        .zip(&noises)
+                        .find(|(rule, noise)| rule.matches(pos, slope, noise.as_ref()))
+                        .map_or(self.default, |(rule, _)| rule.material);
+
+                    voxel_grid.set_material_apron(x, y, z, material);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,452 @@
+//! Ways of storing the samples of a `VoxelGrid`.
+//!
+//! Grids are generated and edited as plain vectors, then compressed to be kept around: chunks
+//! entirely above or below the surface hold a single value, the others a quantized density and
+//! run-length encoded materials. Compressed storages are turned back into vectors as soon as they
+//! are written to.
+
+use std::mem::size_of;
+
+use serde::{Deserialize, Serialize};
+
+use super::voxel_material::VoxelMaterial;
+
+/// Read access shared by every storage backend
+pub trait VoxelStorage<T: Copy> {
+    /// Number of stored samples
+    fn len(&self) -> usize;
+
+    fn get(&self, idx: usize) -> T;
+
+    /// Approximate number of bytes used by the samples
+    fn memory_usage(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn values(&self) -> impl Iterator<Item = T> + '_ {
+        (0..self.len()).map(|idx| self.get(idx))
+    }
+
+    /// Uncompressed copy of the samples
+    fn to_raw(&self) -> Vec<T> {
+        self.values().collect()
+    }
+}
+
+impl<T: Copy> VoxelStorage<T> for Vec<T> {
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn get(&self, idx: usize) -> T {
+        self[idx]
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.capacity() * size_of::<T>()
+    }
+
+    fn values(&self) -> impl Iterator<Item = T> + '_ {
+        self.as_slice().iter().copied()
+    }
+
+    fn to_raw(&self) -> Vec<T> {
+        self.clone()
+    }
+}
+
+/// The same value for every sample
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Uniform<T> {
+    pub value: T,
+    len: usize,
+}
+
+impl<T> Uniform<T> {
+    pub fn new(value: T, len: usize) -> Self {
+        Self { value, len }
+    }
+}
+
+impl<T: Copy> VoxelStorage<T> for Uniform<T> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, idx: usize) -> T {
+        assert!(idx < self.len, "Sample {idx} is out of bounds");
+        self.value
+    }
+
+    fn memory_usage(&self) -> usize {
+        size_of::<Self>()
+    }
+}
+
+/// Integer type densities are quantized to
+pub trait Quantum: Copy {
+    const MIN: f32;
+    const MAX: f32;
+
+    /// Closest quantum to `value`, which is in the `MIN..=MAX` range
+    fn from_f32(value: f32) -> Self;
+
+    fn to_f32(self) -> f32;
+}
+
+impl Quantum for i8 {
+    const MIN: f32 = i8::MIN as f32;
+    const MAX: f32 = i8::MAX as f32;
+
+    fn from_f32(value: f32) -> Self {
+        value.round() as i8
+    }
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+}
+
+impl Quantum for u16 {
+    const MIN: f32 = u16::MIN as f32;
+    const MAX: f32 = u16::MAX as f32;
+
+    fn from_f32(value: f32) -> Self {
+        value.round() as u16
+    }
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+}
+
+/// Densities mapped linearly from their `min..=max` range onto the range of `Q`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantized<Q> {
+    min: f32,
+    max: f32,
+    values: Vec<Q>,
+}
+
+impl<Q: Quantum> Quantized<Q> {
+    pub fn new(samples: &[f32]) -> Self {
+        let (min, max) = samples
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), &value| {
+                (min.min(value), max.max(value))
+            });
+        let range = max - min;
+
+        let values = samples
+            .iter()
+            .map(|&value| match range > 0.0 {
+                true => Q::from_f32(Q::MIN + (value - min) / range * (Q::MAX - Q::MIN)),
+                false => Q::from_f32(Q::MIN),
+            })
+            .collect();
+
+        Self { min, max, values }
+    }
+}
+
+impl<Q: Quantum> VoxelStorage<f32> for Quantized<Q> {
+    fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    fn get(&self, idx: usize) -> f32 {
+        let t = (self.values[idx].to_f32() - Q::MIN) / (Q::MAX - Q::MIN);
+        self.min + t * (self.max - self.min)
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.values.capacity() * size_of::<Q>()
+    }
+}
+
+/// Distinct values listed once in a palette, samples being runs of palette entries
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaletteRle<T> {
+    palette: Vec<T>,
+    runs: Vec<Run>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Run {
+    /// Index right after the last sample of the run
+    end: u32,
+    palette_idx: u16,
+}
+
+impl<T: Copy + PartialEq> PaletteRle<T> {
+    pub fn new(samples: impl Iterator<Item = T>) -> Self {
+        let mut palette: Vec<T> = Vec::new();
+        let mut runs: Vec<Run> = Vec::new();
+
+        for (idx, value) in samples.enumerate() {
+            let palette_idx = match palette.iter().position(|entry| *entry == value) {
+                Some(palette_idx) => palette_idx,
+                None => {
+                    palette.push(value);
+                    palette.len() - 1
+                }
+            } as u16;
+
+            match runs.last_mut() {
+                Some(run) if run.palette_idx == palette_idx => run.end += 1,
+                _ => runs.push(Run {
+                    end: idx as u32 + 1,
+                    palette_idx,
+                }),
+            }
+        }
+
+        Self { palette, runs }
+    }
+
+    pub fn palette(&self) -> &[T] {
+        &self.palette
+    }
+}
+
+impl<T: Copy> VoxelStorage<T> for PaletteRle<T> {
+    fn len(&self) -> usize {
+        self.runs.last().map_or(0, |run| run.end as usize)
+    }
+
+    fn get(&self, idx: usize) -> T {
+        let run = self.runs.partition_point(|run| run.end as usize <= idx);
+        self.palette[self.runs[run].palette_idx as usize]
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.palette.capacity() * size_of::<T>() + self.runs.capacity() * size_of::<Run>()
+    }
+
+    fn values(&self) -> impl Iterator<Item = T> + '_ {
+        let mut start = 0;
+        self.runs.iter().flat_map(move |run| {
+            let len = run.end as usize - start;
+            start = run.end as usize;
+            std::iter::repeat_n(self.palette[run.palette_idx as usize], len)
+        })
+    }
+}
+
+/// How the density of a compressed grid is stored
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum DensityFormat {
+    /// Lossless, 4 bytes per sample
+    Raw,
+    /// 1 byte per sample, coarse enough to move the surface slightly
+    Quantized8,
+    /// 2 bytes per sample
+    #[default]
+    Quantized16,
+}
+
+/// Density samples of a `VoxelGrid`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Density {
+    Raw(Vec<f32>),
+    Uniform(Uniform<f32>),
+    Quantized8(Quantized<i8>),
+    Quantized16(Quantized<u16>),
+}
+
+impl Default for Density {
+    fn default() -> Self {
+        Density::Raw(Vec::new())
+    }
+}
+
+impl Density {
+    pub fn convert(&self, format: DensityFormat) -> Self {
+        let samples = self.to_raw();
+        match format {
+            DensityFormat::Raw => Density::Raw(samples),
+            DensityFormat::Quantized8 => Density::Quantized8(Quantized::new(&samples)),
+            DensityFormat::Quantized16 => Density::Quantized16(Quantized::new(&samples)),
+        }
+    }
+
+    /// Samples as a vector that can be written to, decompressing them first if needed
+    pub fn raw_mut(&mut self) -> &mut Vec<f32> {
+        if !matches!(self, Density::Raw(_)) {
+            *self = Density::Raw(self.to_raw());
+        }
+
+        match self {
+            Density::Raw(data) => data,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl VoxelStorage<f32> for Density {
+    fn len(&self) -> usize {
+        match self {
+            Density::Raw(data) => data.len(),
+            Density::Uniform(data) => data.len(),
+            Density::Quantized8(data) => data.len(),
+            Density::Quantized16(data) => data.len(),
+        }
+    }
+
+    fn get(&self, idx: usize) -> f32 {
+        match self {
+            Density::Raw(data) => data[idx],
+            Density::Uniform(data) => data.get(idx),
+            Density::Quantized8(data) => data.get(idx),
+            Density::Quantized16(data) => data.get(idx),
+        }
+    }
+
+    fn memory_usage(&self) -> usize {
+        match self {
+            Density::Raw(data) => data.memory_usage(),
+            Density::Uniform(data) => data.memory_usage(),
+            Density::Quantized8(data) => data.memory_usage(),
+            Density::Quantized16(data) => data.memory_usage(),
+        }
+    }
+}
+
+/// Material samples of a `VoxelGrid`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Materials {
+    Raw(Vec<VoxelMaterial>),
+    Uniform(Uniform<VoxelMaterial>),
+    Palette(PaletteRle<VoxelMaterial>),
+}
+
+impl Default for Materials {
+    fn default() -> Self {
+        Materials::Raw(Vec::new())
+    }
+}
+
+impl Materials {
+    /// Lossless compression, a single material being stored only once
+    pub fn compress(&self) -> Self {
+        let palette = PaletteRle::new(self.values());
+        match palette.palette() {
+            [material] => Materials::Uniform(Uniform::new(*material, self.len())),
+            _ => Materials::Palette(palette),
+        }
+    }
+
+    /// Samples as a vector that can be written to, decompressing them first if needed
+    pub fn raw_mut(&mut self) -> &mut Vec<VoxelMaterial> {
+        if !matches!(self, Materials::Raw(_)) {
+            *self = Materials::Raw(self.to_raw());
+        }
+
+        match self {
+            Materials::Raw(materials) => materials,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl VoxelStorage<VoxelMaterial> for Materials {
+    fn len(&self) -> usize {
+        match self {
+            Materials::Raw(materials) => materials.len(),
+            Materials::Uniform(materials) => materials.len(),
+            Materials::Palette(materials) => materials.len(),
+        }
+    }
+
+    fn get(&self, idx: usize) -> VoxelMaterial {
+        match self {
+            Materials::Raw(materials) => materials[idx],
+            Materials::Uniform(materials) => materials.get(idx),
+            Materials::Palette(materials) => materials.get(idx),
+        }
+    }
+
+    fn memory_usage(&self) -> usize {
+        match self {
+            Materials::Raw(materials) => materials.memory_usage(),
+            Materials::Uniform(materials) => materials.memory_usage(),
+            Materials::Palette(materials) => materials.memory_usage(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Densities evenly spread from -36 to 36
+    fn densities() -> Vec<f32> {
+        (0..=1000).map(|idx| -36.0 + idx as f32 * 0.072).collect()
+    }
+
+    fn assert_round_trip<Q: Quantum>(samples: &[f32]) {
+        let quantized = Quantized::<Q>::new(samples);
+        // Half a quantum, with some room for rounding
+        let max_error = (samples[samples.len() - 1] - samples[0]) / (Q::MAX - Q::MIN) * 0.5001;
+
+        assert_eq!(quantized.len(), samples.len());
+        for (idx, &value) in samples.iter().enumerate() {
+            let error = (quantized.get(idx) - value).abs();
+            assert!(error <= max_error, "Sample {value} is {error} off");
+        }
+    }
+
+    #[test]
+    fn quantized_densities_stay_within_half_a_quantum() {
+        assert_round_trip::<i8>(&densities());
+        assert_round_trip::<u16>(&densities());
+    }
+
+    #[test]
+    fn palette_runs_give_back_every_sample() {
+        use VoxelMaterial::*;
+
+        let samples = [Grass, Grass, Rock, Rock, Rock, Grass, Snow];
+        let palette = PaletteRle::new(samples.into_iter());
+
+        assert_eq!(palette.len(), samples.len());
+        assert_eq!(palette.palette(), &[Grass, Rock, Snow]);
+        // First, last and single samples of each run
+        for idx in [0, 1, 2, 4, 5, 6] {
+            assert_eq!(palette.get(idx), samples[idx]);
+        }
+        assert!(palette.values().eq(samples.into_iter()));
+    }
+
+    #[test]
+    fn single_material_is_stored_once() {
+        let uniform = Materials::Raw(vec![VoxelMaterial::Rock; 8]).compress();
+        assert_eq!(
+            uniform,
+            Materials::Uniform(Uniform::new(VoxelMaterial::Rock, 8))
+        );
+
+        let mut materials = vec![VoxelMaterial::Rock; 8];
+        materials[3] = VoxelMaterial::Dirt;
+        let palette = Materials::Raw(materials.clone()).compress();
+        assert!(matches!(palette, Materials::Palette(_)));
+        assert!(palette.values().eq(materials.into_iter()));
+    }
+
+    #[test]
+    fn writing_decompresses_the_density() {
+        for format in [DensityFormat::Quantized8, DensityFormat::Quantized16] {
+            let mut density = Density::Raw(densities()).convert(format);
+            let expected = density.to_raw();
+
+            assert_eq!(density.raw_mut(), &expected);
+            assert!(matches!(density, Density::Raw(_)));
+        }
+
+        let mut density = Density::Uniform(Uniform::new(4.0, 8));
+        assert_eq!(density.raw_mut(), &vec![4.0; 8]);
+        assert!(matches!(density, Density::Raw(_)));
+    }
+}
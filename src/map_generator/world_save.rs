@@ -0,0 +1,229 @@
+//! Saving the edited chunks of the world to region files, and loading them back on startup.
+//!
+//! Both happen on the `IoTaskPool` so the game keeps running meanwhile.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    prelude::*,
+    tasks::{block_on, poll_once, IoTaskPool, Task},
+};
+
+use crate::settings::{
+    key_bindings::{action_just_pressed, Action},
+    render::RenderSettings,
+};
+
+use super::{
+    chunk_store::{ChunkStore, StoredChunk},
+    endless_terrain::{ChunkMap, CHUNK_SIZE},
+    generator_asset::TerrainGeneratorDef,
+    map_display::RenderChunk,
+    region::{Region, RegionError},
+    MapGenerator, TerrainGeneratorHandle,
+};
+
+pub struct WorldSavePlugin;
+
+impl Plugin for WorldSavePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WorldSave>()
+            .add_systems(Startup, load_world)
+            .add_systems(
+                Update,
+                (
+                    // Loaded regions are checked against the generator, itself loaded as an asset
+                    poll_world_load
+                        .run_if(resource_exists::<LoadWorldTask>)
+                        .run_if(resource_exists::<MapGenerator>),
+                    // A single save runs at a time
+                    save_world
+                        .run_if(action_just_pressed(Action::SaveWorld))
+                        .run_if(resource_exists::<MapGenerator>)
+                        .run_if(not(resource_exists::<SaveWorldTask>)),
+                    poll_world_save.run_if(resource_exists::<SaveWorldTask>),
+                ),
+            );
+    }
+}
+
+/// Where the world is saved
+#[derive(Resource, Debug)]
+pub struct WorldSave {
+    pub directory: PathBuf,
+}
+
+impl Default for WorldSave {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("saves/world"),
+        }
+    }
+}
+
+#[derive(Resource)]
+struct LoadWorldTask(Task<Vec<(PathBuf, Result<Region, RegionError>)>>);
+
+#[derive(Resource)]
+struct SaveWorldTask(Task<Result<usize, RegionError>>);
+
+fn load_world(mut commands: Commands, world_save: Res<WorldSave>) {
+    let directory = world_save.directory.clone();
+    let task = IoTaskPool::get().spawn(async move { load_regions(&directory) });
+    commands.insert_resource(LoadWorldTask(task));
+}
+
+/// Load every region file of `directory`. A missing directory is an empty world.
+fn load_regions(directory: &Path) -> Vec<(PathBuf, Result<Region, RegionError>)> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "region"))
+        .map(|path| {
+            let region = Region::load(&path);
+            (path, region)
+        })
+        .collect()
+}
+
+/// Move loaded chunks into the `ChunkStore`, regenerating the ones which were already generated
+fn poll_world_load(
+    mut commands: Commands,
+    mut task: ResMut<LoadWorldTask>,
+    mut chunk_store: ResMut<ChunkStore>,
+    chunk_map: Res<ChunkMap>,
+    generator_defs: Res<Assets<TerrainGeneratorDef>>,
+    generator_handle: Res<TerrainGeneratorHandle>,
+) {
+    let Some(regions) = block_on(poll_once(&mut task.0)) else {
+        return;
+    };
+    commands.remove_resource::<LoadWorldTask>();
+
+    let generator = generator_description(&generator_defs, &generator_handle).ok();
+    for (path, region) in regions {
+        let region = match region {
+            Ok(region) => region,
+            Err(err) => {
+                error!("Could not load region '{}': {err}", path.display());
+                continue;
+            }
+        };
+
+        if generator
+            .as_ref()
+            .is_some_and(|generator| *generator != region.generator)
+        {
+            warn!(
+                "Region '{}' was saved with another terrain generator",
+                path.display()
+            );
+        }
+
+        for voxel_grid in region.chunks {
+            let chunk_coord = voxel_grid.chunk_coord();
+            chunk_store.insert_voxel_grid(chunk_coord, voxel_grid);
+            if chunk_map.0.contains_key(&chunk_coord) {
+                commands.add(RenderChunk::new(chunk_coord));
+            }
+        }
+    }
+}
+
+/// Save the world in the background when `Action::SaveWorld` is pressed
+fn save_world(
+    mut commands: Commands,
+    world_save: Res<WorldSave>,
+    chunk_store: Res<ChunkStore>,
+    map_gen: Res<MapGenerator>,
+    render_cfg: Res<RenderSettings>,
+    generator_defs: Res<Assets<TerrainGeneratorDef>>,
+    generator_handle: Res<TerrainGeneratorHandle>,
+) {
+    let directory = world_save.directory.clone();
+    let chunk_store = chunk_store.clone();
+    let map_gen = map_gen.clone();
+    let apron = render_cfg.chunk_apron;
+    // Regions must tell which generator their chunks come from
+    let generator = match generator_description(&generator_defs, &generator_handle) {
+        Ok(generator) => generator,
+        Err(err) => {
+            error!("Could not save the world, its terrain generator can't be described: {err}");
+            return;
+        }
+    };
+
+    let task = IoTaskPool::get().spawn(async move {
+        let regions = bake_regions(&map_gen, &chunk_store, &generator, apron);
+        for region in &regions {
+            region.save(&directory)?;
+        }
+        Ok(regions.len())
+    });
+    commands.insert_resource(SaveWorldTask(task));
+}
+
+fn poll_world_save(mut commands: Commands, mut task: ResMut<SaveWorldTask>) {
+    let Some(result) = block_on(poll_once(&mut task.0)) else {
+        return;
+    };
+    commands.remove_resource::<SaveWorldTask>();
+
+    match result {
+        Ok(region_count) => info!("Saved {region_count} regions"),
+        Err(err) => error!("Could not save the world: {err}"),
+    }
+}
+
+/// Full resolution density of every stored chunk, grouped by region
+fn bake_regions(
+    map_gen: &MapGenerator,
+    chunk_store: &ChunkStore,
+    generator: &str,
+    apron: usize,
+) -> Vec<Region> {
+    let mut regions = HashMap::new();
+
+    for (chunk_coord, stored_chunk) in chunk_store.iter() {
+        let voxel_grid = match stored_chunk {
+            // Chunks loaded from a save and left untouched are saved as they are
+            StoredChunk {
+                voxel_grid: Some(voxel_grid),
+                edits,
+            } if edits.is_empty() => voxel_grid.clone(),
+            _ => {
+                let mut voxel_grid =
+                    map_gen.generate_noise_lod(*chunk_coord, CHUNK_SIZE as usize, 0, apron);
+                stored_chunk.apply(&mut voxel_grid, map_gen.isovalue());
+                voxel_grid
+            }
+        };
+
+        regions
+            .entry(Region::containing(*chunk_coord))
+            .or_insert_with_key(|coord| Region::new(*coord, generator.to_owned()))
+            .chunks
+            .push(voxel_grid);
+    }
+
+    regions.into_values().collect()
+}
+
+/// RON description of the terrain generator in use, the fallback one when its asset could not be
+/// loaded
+fn generator_description(
+    generator_defs: &Assets<TerrainGeneratorDef>,
+    generator_handle: &TerrainGeneratorHandle,
+) -> Result<String, ron::Error> {
+    match generator_defs.get(&generator_handle.0) {
+        Some(generator_def) => ron::ser::to_string(generator_def),
+        None => ron::ser::to_string(&TerrainGeneratorDef::fallback()),
+    }
+}
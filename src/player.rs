@@ -5,7 +5,47 @@ use bevy::{
     window::{CursorGrabMode, PrimaryWindow},
 };
 
-use crate::{fly_cam::FlyCam, settings::MovementSettings};
+use crate::{
+    fly_cam::FlyCam,
+    map_generator::{
+        endless_terrain::ChunkMap,
+        terrain_edit::{Brush, EditMode, TerrainEditEvent},
+        terrain_raycast::TerrainRaycast,
+        MapGenerator,
+    },
+    settings::{
+        key_bindings::{action_just_pressed, Action, ActionInput},
+        MovementSettings,
+    },
+};
+
+/// Furthest distance at which the player can edit the terrain
+pub const EDIT_REACH: f32 = 32.0;
+/// Brushes used to dig and place terrain with the mouse, switched with `Action::CycleBrush`
+const EDIT_BRUSHES: [Brush; 3] = [
+    Brush::Sphere { radius: 3.0 },
+    Brush::Cube {
+        half_extents: Vec3::splat(2.5),
+    },
+    Brush::Cylinder {
+        radius: 2.5,
+        half_height: 3.0,
+    },
+];
+
+/// Height of the camera above the feet of a walking player
+const EYE_HEIGHT: f32 = 1.7;
+/// Distance kept between the body of a walking player and walls
+const PLAYER_RADIUS: f32 = 0.3;
+const GRAVITY: f32 = 20.0;
+const JUMP_SPEED: f32 = 7.0;
+/// Highest ledge a walking player climbs without jumping
+const STEP_HEIGHT: f32 = 0.6;
+/// Steepest slope, in degrees, a walking player can stand on and walk up
+const MAX_SLOPE: f32 = 50.0;
+/// Distance below its feet within which a walking player sticks to the ground, so walking down a
+/// slope doesn't turn into a series of falls
+const GROUND_SNAP: f32 = 0.3;
 
 pub struct PlayerPlugin;
 
@@ -13,7 +53,17 @@ impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<InputState>()
             .add_systems(Startup, spawn_player)
-            .add_systems(Update, (player_movement, player_look));
+            .add_systems(Update, (player_movement, player_look))
+            .add_systems(
+                Update,
+                (
+                    player_edit_terrain,
+                    cycle_edit_brush.run_if(action_just_pressed(Action::CycleBrush)),
+                    toggle_walk_mode.run_if(action_just_pressed(Action::ToggleWalk)),
+                    player_walk,
+                )
+                    .run_if(resource_exists::<MapGenerator>),
+            );
     }
 }
 
@@ -26,37 +76,55 @@ fn spawn_player(mut commands: Commands) {
         },
         FlyCam,
         Player,
+        EditBrush::default(),
     ));
 }
 
 #[derive(Component)]
 pub struct Player;
 
+/// Index in `EDIT_BRUSHES` of the brush the player edits the terrain with
+#[derive(Component, Debug, Default)]
+pub struct EditBrush(usize);
+
+impl EditBrush {
+    pub fn brush(&self) -> Brush {
+        EDIT_BRUSHES[self.0]
+    }
+}
+
+/// The player walks on the terrain instead of flying
+#[derive(Component, Debug, Default)]
+pub struct Walking {
+    vertical_speed: f32,
+    grounded: bool,
+}
+
 fn player_movement(
-    keys: Res<ButtonInput<KeyCode>>,
+    action_input: ActionInput,
     time: Res<Time>,
     settings: Res<MovementSettings>,
-    mut player_q: Query<&mut Transform, With<Player>>,
+    mut player_q: Query<&mut Transform, (With<Player>, Without<Walking>)>,
 ) {
     for mut player_transform in player_q.iter_mut() {
         let mut dir = Vec3::ZERO;
 
-        if keys.pressed(KeyCode::KeyW) {
+        if action_input.pressed(Action::MoveForward) {
             dir += *player_transform.forward();
         }
-        if keys.pressed(KeyCode::KeyS) {
+        if action_input.pressed(Action::MoveBackward) {
             dir += *player_transform.back();
         }
-        if keys.pressed(KeyCode::KeyA) {
+        if action_input.pressed(Action::MoveLeft) {
             dir += *player_transform.left();
         }
-        if keys.pressed(KeyCode::KeyD) {
+        if action_input.pressed(Action::MoveRight) {
             dir += *player_transform.right();
         }
-        if keys.pressed(KeyCode::Space) {
+        if action_input.pressed(Action::Ascend) {
             dir += Vec3::Y;
         }
-        if keys.pressed(KeyCode::ControlLeft) {
+        if action_input.pressed(Action::Descend) {
             dir += Vec3::NEG_Y;
         }
 
@@ -65,6 +133,118 @@ fn player_movement(
     }
 }
 
+/// Switch between flying and walking
+fn toggle_walk_mode(mut commands: Commands, player_q: Query<(Entity, Has<Walking>), With<Player>>) {
+    for (entity, walking) in player_q.iter() {
+        match walking {
+            true => commands.entity(entity).remove::<Walking>(),
+            false => commands.entity(entity).insert(Walking::default()),
+        };
+    }
+}
+
+/// Walk on the surface of the terrain, colliding against its density
+fn player_walk(
+    action_input: ActionInput,
+    time: Res<Time>,
+    settings: Res<MovementSettings>,
+    chunk_map: Res<ChunkMap>,
+    map_gen: Res<MapGenerator>,
+    terrain_raycast: TerrainRaycast,
+    mut player_q: Query<(&mut Transform, &mut Walking), With<Player>>,
+) {
+    let delta = time.delta_seconds();
+    let is_solid = |pos: Vec3| {
+        chunk_map
+            .density_at(pos)
+            .is_some_and(|density| density < map_gen.isovalue())
+    };
+    // Ground from `above` over the feet down to `below` under them
+    let ground = |feet: Vec3, above: f32, below: f32| {
+        let ray = Ray3d {
+            origin: feet + Vec3::Y * above,
+            direction: Dir3::NEG_Y,
+        };
+        terrain_raycast.cast(ray, above + below)
+    };
+    let slope = |normal: Vec3| normal.angle_between(Vec3::Y).to_degrees();
+
+    for (mut player_transform, mut walking) in player_q.iter_mut() {
+        let mut feet = player_transform.translation - Vec3::Y * EYE_HEIGHT;
+
+        // Nothing to stand on until the chunk is generated
+        if chunk_map.density_at(feet).is_none() {
+            continue;
+        }
+
+        let forward = player_transform.forward().with_y(0.0).normalize_or_zero();
+        let right = player_transform.right().with_y(0.0).normalize_or_zero();
+        let mut dir = Vec3::ZERO;
+        if action_input.pressed(Action::MoveForward) {
+            dir += forward;
+        }
+        if action_input.pressed(Action::MoveBackward) {
+            dir -= forward;
+        }
+        if action_input.pressed(Action::MoveLeft) {
+            dir -= right;
+        }
+        if action_input.pressed(Action::MoveRight) {
+            dir += right;
+        }
+        let movement = dir.normalize_or_zero() * settings.walk_speed * delta;
+
+        // Each axis is moved along on its own so that the player slides along walls
+        for axis_movement in [movement.with_z(0.0), movement.with_x(0.0)] {
+            let Some(axis_dir) = axis_movement.try_normalize() else {
+                continue;
+            };
+
+            let target = feet + axis_movement;
+            let blocked = [STEP_HEIGHT + 0.05, EYE_HEIGHT / 2.0, EYE_HEIGHT]
+                .iter()
+                .any(|&height| is_solid(target + Vec3::Y * height + axis_dir * PLAYER_RADIUS));
+            let too_steep = walking.grounded
+                && ground(target, STEP_HEIGHT, GROUND_SNAP)
+                    .is_some_and(|hit| hit.position.y > feet.y && slope(hit.normal) > MAX_SLOPE);
+
+            if !blocked && !too_steep {
+                feet = target;
+            }
+        }
+
+        if walking.grounded && action_input.just_pressed(Action::Jump) {
+            walking.vertical_speed = JUMP_SPEED;
+        }
+        walking.vertical_speed -= GRAVITY * delta;
+
+        let fall = walking.vertical_speed * delta;
+        if fall > 0.0 && is_solid(feet + Vec3::Y * (EYE_HEIGHT + fall)) {
+            // Bumped into a ceiling
+            walking.vertical_speed = 0.0;
+        } else {
+            feet.y += fall;
+        }
+
+        // Land, follow the ground and climb the ledges lower than `STEP_HEIGHT`
+        let snap = if walking.grounded { GROUND_SNAP } else { 0.0 };
+        match ground(feet, STEP_HEIGHT - fall.min(0.0), snap) {
+            Some(hit) if walking.vertical_speed <= 0.0 => {
+                feet.y = hit.position.y;
+                walking.grounded = slope(hit.normal) <= MAX_SLOPE;
+                match walking.grounded {
+                    true => walking.vertical_speed = 0.0,
+                    // Slide down slopes too steep to stand on
+                    false => feet += hit.normal.with_y(0.0) * settings.walk_speed * delta,
+                }
+            }
+            _ => walking.grounded = false,
+        }
+
+        player_transform.translation = feet + Vec3::Y * EYE_HEIGHT;
+    }
+}
+
 /// Keeps track of mouse motion events, pitch, and yaw
 #[derive(Resource, Default)]
 struct InputState {
@@ -104,3 +284,54 @@ fn player_look(
         warn!("Primary window not found for `player_look`!");
     }
 }
+
+/// Dig and place terrain where the player looks, with `Action::Dig` and `Action::Place`
+fn player_edit_terrain(
+    action_input: ActionInput,
+    primary_window: Query<&Window, With<PrimaryWindow>>,
+    player_q: Query<(&Transform, &EditBrush), With<Player>>,
+    terrain_raycast: TerrainRaycast,
+    mut edit_events: EventWriter<TerrainEditEvent>,
+) {
+    let mode = if action_input.just_pressed(Action::Dig) {
+        EditMode::Subtract
+    } else if action_input.just_pressed(Action::Place) {
+        EditMode::Add
+    } else {
+        return;
+    };
+
+    // Clicks only edit the terrain while the cursor is grabbed
+    let Ok(window) = primary_window.get_single() else {
+        return;
+    };
+    if window.cursor.grab_mode == CursorGrabMode::None {
+        return;
+    }
+
+    let Ok((player_t, edit_brush)) = player_q.get_single() else {
+        return;
+    };
+
+    let ray = Ray3d {
+        origin: player_t.translation,
+        direction: player_t.forward(),
+    };
+    let Some(hit) = terrain_raycast.cast(ray, EDIT_REACH) else {
+        return;
+    };
+
+    edit_events.send(TerrainEditEvent {
+        center: hit.position,
+        brush: edit_brush.brush(),
+        mode,
+    });
+}
+
+/// Switch to the next brush of `EDIT_BRUSHES`
+fn cycle_edit_brush(mut player_q: Query<&mut EditBrush, With<Player>>) {
+    for mut edit_brush in player_q.iter_mut() {
+        edit_brush.0 = (edit_brush.0 + 1) % EDIT_BRUSHES.len();
+        info!("Editing the terrain with {:?}", edit_brush.brush());
+    }
+}
@@ -1,6 +1,16 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Default, Resource)]
+#[derive(Resource, Debug, Clone, Deserialize, Serialize)]
 pub struct DebugSetting {
-    display_chunk_gizmos: bool,
+    /// Outline the loaded chunks and the world axes
+    pub display_chunk_gizmos: bool,
+}
+
+impl Default for DebugSetting {
+    fn default() -> Self {
+        Self {
+            display_chunk_gizmos: true,
+        }
+    }
 }
@@ -0,0 +1,254 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::settings_file::{config_directory, load_or_default, FileError};
+
+/// Something the player does with an input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    /// Fly up
+    Ascend,
+    /// Fly down
+    Descend,
+    /// Jump while walking
+    Jump,
+    /// Switch between flying and walking
+    ToggleWalk,
+    /// Grab or release the cursor
+    ToggleCursor,
+    /// Show or hide the F3 overlay
+    ToggleDebug,
+    /// Open or close the settings menu
+    ToggleMenu,
+    /// Remove terrain where the player looks
+    Dig,
+    /// Add terrain where the player looks
+    Place,
+    /// Switch to the next brush digging and placing terrain
+    CycleBrush,
+    SaveWorld,
+}
+
+/// Button an action can be bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum InputButton {
+    Keyboard(KeyCode),
+    Mouse(MouseButton),
+    /// Button of any connected gamepad
+    Gamepad(GamepadButtonType),
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum KeyBindingsError {
+    #[error("Could not access key bindings file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid key bindings: {0}")]
+    Parse(#[from] ron::error::SpannedError),
+    #[error("Could not serialize key bindings: {0}")]
+    Serialize(#[from] ron::Error),
+}
+
+impl FileError for KeyBindingsError {
+    fn is_not_found(&self) -> bool {
+        matches!(self, Self::Io(err) if err.kind() == std::io::ErrorKind::NotFound)
+    }
+}
+
+/// Inputs bound to each action. Actions missing from a loaded file keep their default bindings.
+#[derive(Resource, Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct KeyBindings {
+    /// Where the bindings are loaded from and saved to
+    #[serde(skip)]
+    pub path: PathBuf,
+    bindings: BTreeMap<Action, Vec<InputButton>>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        use InputButton::*;
+
+        let bindings = [
+            (
+                Action::MoveForward,
+                vec![Keyboard(KeyCode::KeyW), Gamepad(GamepadButtonType::DPadUp)],
+            ),
+            (
+                Action::MoveBackward,
+                vec![
+                    Keyboard(KeyCode::KeyS),
+                    Gamepad(GamepadButtonType::DPadDown),
+                ],
+            ),
+            (
+                Action::MoveLeft,
+                vec![
+                    Keyboard(KeyCode::KeyA),
+                    Gamepad(GamepadButtonType::DPadLeft),
+                ],
+            ),
+            (
+                Action::MoveRight,
+                vec![
+                    Keyboard(KeyCode::KeyD),
+                    Gamepad(GamepadButtonType::DPadRight),
+                ],
+            ),
+            (
+                Action::Ascend,
+                vec![Keyboard(KeyCode::Space), Gamepad(GamepadButtonType::South)],
+            ),
+            (
+                Action::Descend,
+                vec![
+                    Keyboard(KeyCode::ControlLeft),
+                    Gamepad(GamepadButtonType::East),
+                ],
+            ),
+            (
+                Action::Jump,
+                vec![Keyboard(KeyCode::Space), Gamepad(GamepadButtonType::South)],
+            ),
+            (
+                Action::ToggleWalk,
+                vec![Keyboard(KeyCode::KeyF), Gamepad(GamepadButtonType::North)],
+            ),
+            (Action::ToggleCursor, vec![Keyboard(KeyCode::AltLeft)]),
+            (
+                Action::ToggleDebug,
+                vec![Keyboard(KeyCode::F3), Gamepad(GamepadButtonType::Select)],
+            ),
+            (
+                Action::ToggleMenu,
+                vec![Keyboard(KeyCode::Escape), Gamepad(GamepadButtonType::Start)],
+            ),
+            (
+                Action::Dig,
+                vec![
+                    Mouse(MouseButton::Left),
+                    Gamepad(GamepadButtonType::RightTrigger2),
+                ],
+            ),
+            (
+                Action::Place,
+                vec![
+                    Mouse(MouseButton::Right),
+                    Gamepad(GamepadButtonType::LeftTrigger2),
+                ],
+            ),
+            (
+                Action::CycleBrush,
+                vec![
+                    Keyboard(KeyCode::KeyB),
+                    Gamepad(GamepadButtonType::RightTrigger),
+                ],
+            ),
+            (Action::SaveWorld, vec![Keyboard(KeyCode::F5)]),
+        ];
+
+        Self {
+            path: config_directory().join("key_bindings.ron"),
+            bindings: bindings.into_iter().collect(),
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn get(&self, action: Action) -> &[InputButton] {
+        self.bindings.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, KeyBindingsError> {
+        let mut key_bindings: Self = ron::from_str(&fs::read_to_string(path)?)?;
+        let defaults = Self::default();
+        for (action, inputs) in defaults.bindings {
+            key_bindings.bindings.entry(action).or_insert(inputs);
+        }
+        key_bindings.path = path.to_owned();
+        Ok(key_bindings)
+    }
+
+    pub fn save(&self) -> Result<(), KeyBindingsError> {
+        if let Some(directory) = self.path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        let ron = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(&self.path, ron)?;
+        Ok(())
+    }
+}
+
+/// State of the actions, whatever they are bound to
+#[derive(SystemParam)]
+pub struct ActionInput<'w> {
+    key_bindings: Res<'w, KeyBindings>,
+    keys: Res<'w, ButtonInput<KeyCode>>,
+    mouse: Res<'w, ButtonInput<MouseButton>>,
+    gamepads: Res<'w, Gamepads>,
+    gamepad_buttons: Res<'w, ButtonInput<GamepadButton>>,
+}
+
+impl ActionInput<'_> {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.key_bindings
+            .get(action)
+            .iter()
+            .any(|input| match *input {
+                InputButton::Keyboard(key_code) => self.keys.pressed(key_code),
+                InputButton::Mouse(button) => self.mouse.pressed(button),
+                InputButton::Gamepad(button_type) => self
+                    .on_every_gamepad(button_type)
+                    .any(|button| self.gamepad_buttons.pressed(button)),
+            })
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.key_bindings
+            .get(action)
+            .iter()
+            .any(|input| match *input {
+                InputButton::Keyboard(key_code) => self.keys.just_pressed(key_code),
+                InputButton::Mouse(button) => self.mouse.just_pressed(button),
+                InputButton::Gamepad(button_type) => self
+                    .on_every_gamepad(button_type)
+                    .any(|button| self.gamepad_buttons.just_pressed(button)),
+            })
+    }
+
+    /// `button_type` on every connected gamepad
+    fn on_every_gamepad(
+        &self,
+        button_type: GamepadButtonType,
+    ) -> impl Iterator<Item = GamepadButton> + '_ {
+        self.gamepads
+            .iter()
+            .map(move |gamepad| GamepadButton::new(gamepad, button_type))
+    }
+}
+
+/// Run condition true on the frame an input bound to `action` is pressed
+pub fn action_just_pressed(action: Action) -> impl FnMut(ActionInput) -> bool + Clone {
+    move |input: ActionInput| input.just_pressed(action)
+}
+
+pub(super) fn load_key_bindings() -> KeyBindings {
+    load_or_default(KeyBindings::load(&KeyBindings::default().path))
+}
+
+pub(super) fn save_key_bindings(key_bindings: Res<KeyBindings>) {
+    if let Err(err) = key_bindings.save() {
+        error!("{err}");
+    }
+}
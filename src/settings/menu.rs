@@ -0,0 +1,311 @@
+//! Settings menu opened with `Action::ToggleMenu`, editing the settings resources while playing.
+
+use bevy::{
+    color::palettes::css::BLACK,
+    prelude::*,
+    window::{CursorGrabMode, PrimaryWindow},
+};
+
+use crate::fly_cam::toggle_grab_cursor;
+
+use super::{
+    debug::DebugSetting,
+    key_bindings::{Action, ActionInput},
+    render::RenderSettings,
+    MovementSettings,
+};
+
+/// Furthest render distance the menu goes up to, in chunks
+const MAX_RENDER_DISTANCE: u32 = 32;
+/// Factor the sensitivity is multiplied or divided by on each click
+const SENSITIVITY_STEP: f32 = 1.25;
+
+const BUTTON_COLOR: Color = Color::srgb(0.15, 0.15, 0.15);
+const HOVERED_BUTTON_COLOR: Color = Color::srgb(0.3, 0.3, 0.3);
+const PRESSED_BUTTON_COLOR: Color = Color::srgb(0.45, 0.45, 0.45);
+
+#[derive(Component)]
+pub struct SettingsMenu;
+
+/// Setting shown by a text of the menu
+#[derive(Component, Debug, Clone, Copy)]
+pub enum MenuValue {
+    HorizontalRenderDistance,
+    VerticalRenderDistance,
+    Sensitivity,
+    ChunkGizmos,
+}
+
+impl MenuValue {
+    fn text(
+        &self,
+        render_cfg: &RenderSettings,
+        movement_cfg: &MovementSettings,
+        debug_cfg: &DebugSetting,
+    ) -> String {
+        match self {
+            MenuValue::HorizontalRenderDistance => format!("{}", render_cfg.render_distance.0),
+            MenuValue::VerticalRenderDistance => format!("{}", render_cfg.render_distance.1),
+            MenuValue::Sensitivity => format!("{:.5}", movement_cfg.sensitivity),
+            MenuValue::ChunkGizmos => match debug_cfg.display_chunk_gizmos {
+                true => "On".to_owned(),
+                false => "Off".to_owned(),
+            },
+        }
+    }
+}
+
+/// What clicking a button of the menu does
+#[derive(Component, Debug, Clone, Copy)]
+pub enum MenuButton {
+    /// Add to the horizontal render distance
+    HorizontalRenderDistance(i32),
+    /// Add to the vertical render distance
+    VerticalRenderDistance(i32),
+    /// Multiply the sensitivity
+    Sensitivity(f32),
+    ChunkGizmos,
+    Resume,
+}
+
+fn spawn_menu(commands: &mut Commands) {
+    let style = TextStyle {
+        font_size: 20.0,
+        ..default()
+    };
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .insert(SettingsMenu)
+        .with_children(|builder| {
+            builder
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Stretch,
+                        row_gap: Val::Px(8.),
+                        padding: UiRect::all(Val::Px(16.)),
+                        ..default()
+                    },
+                    background_color: Color::from(BLACK.with_alpha(0.75)).into(),
+                    ..default()
+                })
+                .with_children(|builder| {
+                    builder.spawn(TextBundle::from_section(
+                        "Settings",
+                        TextStyle {
+                            font_size: 28.0,
+                            ..default()
+                        },
+                    ));
+
+                    // -------------------- Render distance --------------------
+                    spawn_row(
+                        builder,
+                        "Horizontal render distance",
+                        MenuValue::HorizontalRenderDistance,
+                        &[
+                            ("-", MenuButton::HorizontalRenderDistance(-1)),
+                            ("+", MenuButton::HorizontalRenderDistance(1)),
+                        ],
+                        &style,
+                    );
+                    spawn_row(
+                        builder,
+                        "Vertical render distance",
+                        MenuValue::VerticalRenderDistance,
+                        &[
+                            ("-", MenuButton::VerticalRenderDistance(-1)),
+                            ("+", MenuButton::VerticalRenderDistance(1)),
+                        ],
+                        &style,
+                    );
+
+                    // -------------------- Sensitivity --------------------
+                    spawn_row(
+                        builder,
+                        "Mouse sensitivity",
+                        MenuValue::Sensitivity,
+                        &[
+                            ("-", MenuButton::Sensitivity(SENSITIVITY_STEP.recip())),
+                            ("+", MenuButton::Sensitivity(SENSITIVITY_STEP)),
+                        ],
+                        &style,
+                    );
+
+                    // -------------------- Debug --------------------
+                    spawn_row(
+                        builder,
+                        "Chunk gizmos",
+                        MenuValue::ChunkGizmos,
+                        &[("Toggle", MenuButton::ChunkGizmos)],
+                        &style,
+                    );
+
+                    spawn_button(builder, "Resume", MenuButton::Resume, &style);
+                });
+        });
+}
+
+/// Label and value of a setting, followed by the buttons changing it
+fn spawn_row(
+    builder: &mut ChildBuilder,
+    label: &str,
+    value: MenuValue,
+    buttons: &[(&str, MenuButton)],
+    style: &TextStyle,
+) {
+    builder
+        .spawn(NodeBundle {
+            style: Style {
+                align_items: AlignItems::Center,
+                column_gap: Val::Px(8.),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|builder| {
+            builder.spawn(
+                TextBundle::from_section(label, style.clone()).with_style(Style {
+                    flex_grow: 1.,
+                    ..default()
+                }),
+            );
+            builder.spawn((
+                TextBundle::from_section("", style.clone()).with_style(Style {
+                    min_width: Val::Px(80.),
+                    ..default()
+                }),
+                value,
+            ));
+            for (text, button) in buttons {
+                spawn_button(builder, text, *button, style);
+            }
+        });
+}
+
+fn spawn_button(builder: &mut ChildBuilder, text: &str, button: MenuButton, style: &TextStyle) {
+    builder
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    justify_content: JustifyContent::Center,
+                    padding: UiRect::axes(Val::Px(12.), Val::Px(4.)),
+                    ..default()
+                },
+                background_color: BUTTON_COLOR.into(),
+                ..default()
+            },
+            button,
+        ))
+        .with_children(|builder| {
+            builder.spawn(TextBundle::from_section(text, style.clone()));
+        });
+}
+
+/// Open or close the menu, releasing the cursor while it is open
+fn toggle_menu(commands: &mut Commands, menu_e: Option<Entity>, window: &mut Window) {
+    let is_grabbed = window.cursor.grab_mode != CursorGrabMode::None;
+    match menu_e {
+        Some(menu_e) => {
+            commands.entity(menu_e).despawn_recursive();
+            if !is_grabbed {
+                toggle_grab_cursor(window);
+            }
+        }
+        None => {
+            spawn_menu(commands);
+            if is_grabbed {
+                toggle_grab_cursor(window);
+            }
+        }
+    }
+}
+
+pub(super) fn toggle_menu_visibility(
+    mut commands: Commands,
+    action_input: ActionInput,
+    menu_q: Query<Entity, With<SettingsMenu>>,
+    mut primary_window: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    if !action_input.just_pressed(Action::ToggleMenu) {
+        return;
+    }
+
+    if let Ok(mut window) = primary_window.get_single_mut() {
+        toggle_menu(&mut commands, menu_q.get_single().ok(), &mut window);
+    } else {
+        warn!("Primary window not found for `toggle_menu_visibility`!");
+    }
+}
+
+/// Apply the buttons clicked in the menu. Only changed settings are touched, so systems reacting
+/// to them (like the chunk levels of detail) don't run every frame.
+pub(super) fn press_menu_buttons(
+    mut commands: Commands,
+    mut buttons_q: Query<(&Interaction, &MenuButton, &mut BackgroundColor), Changed<Interaction>>,
+    menu_q: Query<Entity, With<SettingsMenu>>,
+    mut primary_window: Query<&mut Window, With<PrimaryWindow>>,
+    mut render_cfg: ResMut<RenderSettings>,
+    mut movement_cfg: ResMut<MovementSettings>,
+    mut debug_cfg: ResMut<DebugSetting>,
+) {
+    let add_distance = |distance: u32, step: i32| {
+        distance
+            .saturating_add_signed(step)
+            .min(MAX_RENDER_DISTANCE)
+    };
+
+    for (interaction, button, mut background_color) in buttons_q.iter_mut() {
+        *background_color = match interaction {
+            Interaction::Pressed => PRESSED_BUTTON_COLOR,
+            Interaction::Hovered => HOVERED_BUTTON_COLOR,
+            Interaction::None => BUTTON_COLOR,
+        }
+        .into();
+
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+
+        match *button {
+            MenuButton::HorizontalRenderDistance(step) => {
+                render_cfg.render_distance.0 = add_distance(render_cfg.render_distance.0, step);
+            }
+            MenuButton::VerticalRenderDistance(step) => {
+                render_cfg.render_distance.1 = add_distance(render_cfg.render_distance.1, step);
+            }
+            MenuButton::Sensitivity(factor) => movement_cfg.sensitivity *= factor,
+            MenuButton::ChunkGizmos => {
+                debug_cfg.display_chunk_gizmos = !debug_cfg.display_chunk_gizmos;
+            }
+            MenuButton::Resume => {
+                let Ok(mut window) = primary_window.get_single_mut() else {
+                    continue;
+                };
+                toggle_menu(&mut commands, menu_q.get_single().ok(), &mut window);
+            }
+        }
+    }
+}
+
+pub(super) fn update_menu_values(
+    render_cfg: Res<RenderSettings>,
+    movement_cfg: Res<MovementSettings>,
+    debug_cfg: Res<DebugSetting>,
+    mut values_q: Query<(&mut Text, &MenuValue)>,
+) {
+    for (mut text, value) in values_q.iter_mut() {
+        text.sections[0].value = value.text(&render_cfg, &movement_cfg, &debug_cfg);
+    }
+}
@@ -1,26 +1,48 @@
 use bevy::prelude::*;
-use debug::DebugSetting;
-use render::RenderSettings;
+use key_bindings::{load_key_bindings, save_key_bindings};
+use menu::{press_menu_buttons, toggle_menu_visibility, update_menu_values};
+use render::TerrainMaterialSettings;
+use serde::{Deserialize, Serialize};
+use settings_file::{app_exiting, load_settings, save_settings, SettingsFile};
 
 pub mod debug;
 pub mod key_bindings;
+pub mod menu;
 pub mod render;
+pub mod settings_file;
 
 pub struct SettingPlugin;
 
 impl Plugin for SettingPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<MovementSettings>()
-            .init_resource::<DebugSetting>()
-            .init_resource::<RenderSettings>();
+        let settings_file = SettingsFile::default();
+        let settings = load_settings(&settings_file);
+
+        app.insert_resource(settings.movement)
+            .insert_resource(settings.debug)
+            .insert_resource(settings.render)
+            .insert_resource(settings_file)
+            .init_resource::<TerrainMaterialSettings>()
+            .insert_resource(load_key_bindings())
+            .add_systems(
+                Update,
+                (
+                    toggle_menu_visibility,
+                    press_menu_buttons,
+                    update_menu_values,
+                ),
+            )
+            .add_systems(Last, (save_settings, save_key_bindings).run_if(app_exiting));
     }
 }
 
 /// Mouse sensitivity and movement speed
-#[derive(Resource)]
+#[derive(Resource, Debug, Clone, Deserialize, Serialize)]
 pub struct MovementSettings {
     pub sensitivity: f32,
+    /// Flying speed
     pub speed: f32,
+    pub walk_speed: f32,
 }
 
 impl Default for MovementSettings {
@@ -28,6 +50,7 @@ impl Default for MovementSettings {
         Self {
             sensitivity: 0.00012,
             speed: 36.0,
+            walk_speed: 6.0,
         }
     }
 }
@@ -1,14 +1,73 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Resource)]
+use crate::map_generator::voxel_storage::DensityFormat;
+
+#[derive(Resource, Debug, Clone, Deserialize, Serialize)]
 pub struct RenderSettings {
     pub render_distance: (u32, u32),
+    pub normal_mode: NormalMode,
+    /// Chunk distance up to which each level of detail is used. Chunks further than the last one
+    /// use the coarsest level.
+    pub lod_distances: [u32; 3],
+    /// Number of samples taken beyond each side of a chunk, used for the normals on its border
+    pub chunk_apron: usize,
+    /// How the density of generated chunks is kept in memory once they are meshed
+    pub density_format: DensityFormat,
 }
 
 impl Default for RenderSettings {
     fn default() -> Self {
         Self {
             render_distance: (0, 0),
+            normal_mode: NormalMode::Gradient,
+            lod_distances: [2, 4, 8],
+            chunk_apron: 1,
+            density_format: DensityFormat::default(),
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Level of detail of a chunk `distance` chunks away from the player
+    pub fn lod_level(&self, distance: u32) -> u8 {
+        self.lod_distances
+            .iter()
+            .filter(|&&lod_distance| distance > lod_distance)
+            .count() as u8
+    }
+}
+
+/// Appearance of the terrain
+#[derive(Resource, Debug, Clone)]
+pub struct TerrainMaterialSettings {
+    /// Image stacking one square texture per `VoxelMaterial` vertically, in declaration order.
+    /// The terrain is coloured by its vertices when it is `None` or can't be loaded.
+    pub textures: Option<String>,
+    /// Number of texture repetitions per world unit
+    pub texture_scale: f32,
+    /// How sharply the 3 planar projections are blended, higher values giving crisper transitions
+    pub blend_sharpness: f32,
+}
+
+impl Default for TerrainMaterialSettings {
+    fn default() -> Self {
+        Self {
+            textures: Some("textures/terrain.png".to_owned()),
+            texture_scale: 0.125,
+            blend_sharpness: 4.0,
         }
     }
 }
+
+/// How vertex normals of terrain meshes are computed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum NormalMode {
+    /// One normal per face, vertices are not shared between triangles
+    Flat,
+    /// Area-weighted average of the normals of all faces sharing a vertex
+    AveragedFace,
+    /// Gradient of the density field, continuous across chunk borders
+    #[default]
+    Gradient,
+}
@@ -0,0 +1,157 @@
+//! User settings saved to a TOML file in the platform config directory.
+//!
+//! Every setting is read on its own, so an unknown or invalid one is reported and keeps its
+//! default value without discarding the rest of the file.
+
+use std::{fmt::Display, fs, io, path::PathBuf};
+
+use bevy::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{debug::DebugSetting, render::RenderSettings, MovementSettings};
+
+/// Directory holding the settings and key bindings files, `config` when the platform has none
+pub fn config_directory() -> PathBuf {
+    dirs::config_dir().map_or_else(
+        || PathBuf::from("config"),
+        |directory| directory.join(env!("CARGO_PKG_NAME")),
+    )
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum SettingsFileError {
+    #[error("Could not access settings file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid settings file: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Could not serialize settings: {0}")]
+    Serialize(#[from] toml::ser::Error),
+}
+
+/// Error loading a file which is expected to be missing until it is first saved
+pub trait FileError: Display {
+    fn is_not_found(&self) -> bool;
+}
+
+impl FileError for SettingsFileError {
+    fn is_not_found(&self) -> bool {
+        matches!(self, Self::Io(err) if err.kind() == io::ErrorKind::NotFound)
+    }
+}
+
+/// Settings stored in the file, one table each
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Settings {
+    pub movement: MovementSettings,
+    pub render: RenderSettings,
+    pub debug: DebugSetting,
+}
+
+/// Where the settings are loaded from and saved to
+#[derive(Resource, Debug)]
+pub struct SettingsFile {
+    pub path: PathBuf,
+}
+
+impl Default for SettingsFile {
+    fn default() -> Self {
+        Self {
+            path: config_directory().join("settings.toml"),
+        }
+    }
+}
+
+impl SettingsFile {
+    /// Settings in the file. Unknown and invalid settings are reported as warnings and replaced
+    /// by their default.
+    pub fn load(&self) -> Result<Settings, SettingsFileError> {
+        let mut file: toml::Table = fs::read_to_string(&self.path)?.parse()?;
+
+        let settings = Settings {
+            movement: read_table(&mut file, "movement"),
+            render: read_table(&mut file, "render"),
+            debug: read_table(&mut file, "debug"),
+        };
+        for name in file.keys() {
+            warn!("Unknown settings table [{name}], ignoring it");
+        }
+
+        Ok(settings)
+    }
+
+    pub fn save(&self, settings: &Settings) -> Result<(), SettingsFileError> {
+        if let Some(directory) = self.path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        fs::write(&self.path, toml::to_string(settings)?)?;
+        Ok(())
+    }
+}
+
+/// Settings of the `name` table, removed from `file`
+fn read_table<T: Default + Serialize + DeserializeOwned>(file: &mut toml::Table, name: &str) -> T {
+    let Some(values) = file.remove(name) else {
+        return T::default();
+    };
+    let toml::Value::Table(values) = values else {
+        warn!("Settings [{name}] should be a table, using the default ones");
+        return T::default();
+    };
+
+    let mut settings =
+        toml::Table::try_from(T::default()).expect("Default settings should serialize to a table");
+    for (key, value) in values {
+        if !settings.contains_key(&key) {
+            warn!("Unknown setting {name}.{key}, ignoring it");
+            continue;
+        }
+
+        // Each value is checked with the others valid, so the error points at it
+        let mut candidate = settings.clone();
+        candidate.insert(key.clone(), value);
+        match candidate.clone().try_into::<T>() {
+            Ok(_) => settings = candidate,
+            Err(err) => warn!("Invalid setting {name}.{key}, using the default value: {err}"),
+        }
+    }
+
+    settings.try_into().unwrap_or_default()
+}
+
+/// Loaded value, or the default one when the file is missing or can't be loaded. Only the latter
+/// is reported, a missing file being expected on the first run.
+pub(super) fn load_or_default<T: Default, E: FileError>(loaded: Result<T, E>) -> T {
+    loaded.unwrap_or_else(|err| {
+        if !err.is_not_found() {
+            warn!("{err}, using the default ones");
+        }
+        T::default()
+    })
+}
+
+/// Run condition true on the frame the app exits, so the changes made while playing are saved
+pub(super) fn app_exiting(mut exit_events: EventReader<AppExit>) -> bool {
+    exit_events.read().last().is_some()
+}
+
+pub(super) fn load_settings(settings_file: &SettingsFile) -> Settings {
+    load_or_default(settings_file.load())
+}
+
+pub(super) fn save_settings(
+    settings_file: Res<SettingsFile>,
+    movement: Res<MovementSettings>,
+    render: Res<RenderSettings>,
+    debug: Res<DebugSetting>,
+) {
+    let settings = Settings {
+        movement: movement.clone(),
+        render: render.clone(),
+        debug: debug.clone(),
+    };
+    if let Err(err) = settings_file.save(&settings) {
+        error!("{err}");
+    }
+}